@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use super::ext_oscjson::OscJsonNode;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// VRChat's own OSC config: `usr_*/Avatars/avtr_*.json`, written the first time
+/// an avatar is worn with OSC enabled. A fallback for when OSCQuery can't be reached.
+pub struct AvatarFiles {
+    dir: Arc<str>,
+    modified: HashMap<PathBuf, SystemTime>,
+    next_scan: Instant,
+}
+
+impl AvatarFiles {
+    pub fn new(dir: &str) -> Self {
+        let mut me = Self {
+            dir: dir.trim_end_matches('/').into(),
+            modified: HashMap::new(),
+            next_scan: Instant::now(),
+        };
+        // only report files that change from now on
+        me.scan();
+        me
+    }
+
+    fn files(&self, avatar_id: &str) -> Vec<PathBuf> {
+        let pattern = format!("{}/usr_*/Avatars/{}.json", self.dir, avatar_id);
+        match glob::glob(&pattern) {
+            Ok(paths) => paths.flatten().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Ids of the avatars whose files appeared or changed since the last call.
+    pub fn scan(&mut self) -> Vec<String> {
+        if self.next_scan > Instant::now() {
+            return Vec::new();
+        }
+        self.next_scan = Instant::now() + SCAN_INTERVAL;
+
+        let mut changed = Vec::new();
+        for path in self.files("avtr_*") {
+            let Ok(modified) = fs::metadata(&path).and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if self.modified.insert(path.clone(), modified) == Some(modified) {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+                changed.push(id.to_string());
+            }
+        }
+        changed
+    }
+
+    /// The parameter tree of `avatar_id`, from the most recently written file if
+    /// several VRChat accounts have one.
+    pub fn load(&self, avatar_id: &str) -> Option<OscJsonNode> {
+        if !is_avatar_id(avatar_id) {
+            return None;
+        }
+
+        let path = self
+            .files(avatar_id)
+            .into_iter()
+            .max_by_key(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())?;
+
+        let result = fs::read_to_string(&path)
+            .context("could not read file")
+            .and_then(|json| parse(&json));
+        match result {
+            Ok(node) => {
+                log::info!("Using VRChat's OSC config at {}", path.display());
+                Some(node)
+            }
+            Err(e) => {
+                log::warn!("{}: {e:#}", path.display());
+                None
+            }
+        }
+    }
+}
+
+/// Whether `id` is safe to use in a file name.
+pub fn is_avatar_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[derive(Deserialize)]
+struct AvatarFile {
+    parameters: Vec<AvatarFileParam>,
+}
+
+#[derive(Deserialize)]
+struct AvatarFileParam {
+    input: Option<AvatarFileEndpoint>,
+    output: Option<AvatarFileEndpoint>,
+}
+
+#[derive(Deserialize)]
+struct AvatarFileEndpoint {
+    address: String,
+    #[serde(rename = "type")]
+    data_type: String,
+}
+
+fn container(full_path: &str) -> OscJsonNode {
+    OscJsonNode {
+        full_path: full_path.into(),
+        access: 0,
+        data_type: None,
+        contents: Some(HashMap::new()),
+    }
+}
+
+/// Builds the same `/avatar` tree VRChat's OSCQuery server would serve.
+pub fn parse(json: &str) -> anyhow::Result<OscJsonNode> {
+    // VRChat writes these with a byte order mark
+    let file: AvatarFile = serde_json::from_str(json.trim_start_matches('\u{feff}'))
+        .context("not a VRChat avatar OSC config")?;
+
+    let mut root = container("/avatar");
+    root.contents.as_mut().unwrap().insert(
+        "change".into(),
+        OscJsonNode {
+            full_path: "/avatar/change".into(),
+            access: 3,
+            data_type: Some("s".into()),
+            contents: None,
+        },
+    );
+
+    for param in file.parameters.iter() {
+        let Some(endpoint) = param.output.as_ref().or(param.input.as_ref()) else {
+            continue;
+        };
+        let Some(name) = endpoint.address.strip_prefix("/avatar/") else {
+            continue;
+        };
+        let data_type = match endpoint.data_type.as_str() {
+            "Bool" => "T",
+            "Int" => "i",
+            "Float" => "f",
+            _ => continue,
+        };
+        let access = (param.output.is_some() as i32) | (param.input.is_some() as i32) << 1;
+
+        let mut node = &mut root;
+        let mut full_path = String::from("/avatar");
+        let parts: Vec<&str> = name.split('/').collect();
+        for (i, part) in parts.iter().enumerate() {
+            full_path = format!("{}/{}", full_path, part);
+            let Some(contents) = node.contents.as_mut() else {
+                break;
+            };
+            node = contents.entry((*part).into()).or_insert_with(|| {
+                if i + 1 == parts.len() {
+                    OscJsonNode {
+                        full_path: full_path.as_str().into(),
+                        access,
+                        data_type: Some(data_type.into()),
+                        contents: None,
+                    }
+                } else {
+                    container(&full_path)
+                }
+            });
+        }
+    }
+
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avatar_file_becomes_an_oscquery_tree() {
+        let json = "\u{feff}{
+            \"id\": \"avtr_1234\",
+            \"name\": \"Test\",
+            \"parameters\": [
+                {
+                    \"name\": \"VSync\",
+                    \"input\": { \"address\": \"/avatar/parameters/VSync\", \"type\": \"Bool\" },
+                    \"output\": { \"address\": \"/avatar/parameters/VSync\", \"type\": \"Bool\" }
+                },
+                {
+                    \"name\": \"FT/v2/JawOpen\",
+                    \"input\": { \"address\": \"/avatar/parameters/FT/v2/JawOpen\", \"type\": \"Float\" },
+                    \"output\": { \"address\": \"/avatar/parameters/FT/v2/JawOpen\", \"type\": \"Float\" }
+                },
+                {
+                    \"name\": \"VelocityX\",
+                    \"output\": { \"address\": \"/avatar/parameters/VelocityX\", \"type\": \"Float\" }
+                }
+            ]
+        }";
+
+        let root = parse(json).unwrap();
+        assert!(root.has_vsync());
+
+        let jaw = root.get("parameters/FT/v2/JawOpen").unwrap();
+        assert_eq!(&*jaw.full_path, "/avatar/parameters/FT/v2/JawOpen");
+        assert_eq!(jaw.data_type.as_deref(), Some("f"));
+        assert_eq!(jaw.access, 3);
+        assert_eq!(
+            &*root.get("parameters/FT").unwrap().full_path,
+            "/avatar/parameters/FT"
+        );
+
+        assert_eq!(root.get("parameters/VelocityX").unwrap().access, 1);
+    }
+
+    #[test]
+    fn finds_new_avatar_files() {
+        let dir = std::env::temp_dir().join(format!("oscavmgr-avatars-{}", std::process::id()));
+        let avatars = dir.join("usr_0000/Avatars");
+        fs::create_dir_all(&avatars).unwrap();
+
+        let mut files = AvatarFiles::new(dir.to_str().unwrap());
+        assert!(files.load("avtr_1234").is_none());
+
+        fs::write(avatars.join("avtr_1234.json"), "{\"parameters\": []}").unwrap();
+        files.next_scan = Instant::now();
+        assert_eq!(files.scan(), ["avtr_1234"]);
+        assert!(files.load("avtr_1234").is_some());
+        assert!(files.load("../avtr_1234").is_none());
+
+        files.next_scan = Instant::now();
+        assert!(files.scan().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1016 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{Args, Command, FaceSetup};
+
+use super::{
+    ext_tracking::curves::{curve_shape, curve_table, ShapeCurve},
+    folders::CONFIG_DIR,
+    router::RouteTarget,
+};
+
+const FILE_NAME: &str = "oscavmgr.toml";
+
+/// Everything that can be tuned without recompiling.
+///
+/// Loaded from `~/.config/oscavmgr.toml`, then overridden by any flags given on the command line.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// OSC-JSON avatar file to load on startup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    pub face: FaceSetup,
+    pub osc: OscConfig,
+    pub router: RouterConfig,
+    pub tracking: TrackingConfig,
+    pub calibration: CalibrationConfig,
+    pub filter: FilterConfig,
+    pub tracking_loss: TrackingLossConfig,
+    /// Response curves, by unified shape name
+    pub curves: BTreeMap<String, ShapeCurve>,
+    pub formulas: FormulasConfig,
+    pub eye_output: EyeOutputConfig,
+    pub autopilot: AutoPilotConfig,
+    pub storage: StorageConfig,
+    pub babble: BabbleConfig,
+    pub compositor: CompositorConfig,
+    pub vmc: VmcConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OscConfig {
+    /// Listen on 0.0.0.0 instead of localhost
+    pub expose: bool,
+    /// Listen, send and advertise on this address or network interface (e.g. `192.168.1.20`,
+    /// `fd00::20` or `eth0`) instead. Takes precedence over `expose`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind: Option<String>,
+    /// Port VRChat listens on
+    pub vrc_port: u16,
+    /// Address VRChat listens on, when it runs on another machine or in a VM.
+    /// Defaults to the host of `oscquery_url`, or to this machine unless `bind` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vrc_ip: Option<IpAddr>,
+    /// Port oscavmgr listens on
+    pub osc_port: u16,
+    /// Advertise oscavmgr to VRChat through OSCQuery
+    pub advertise: bool,
+    /// Fetch avatar parameters from this OSCQuery server (e.g. `http://127.0.0.1:9001`)
+    /// instead of looking VRChat up through mDNS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oscquery_url: Option<String>,
+    /// VRChat's OSC config folder, read when OSCQuery can't be reached.
+    /// Defaults to the one in Steam's default library.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vrchat_osc_dir: Option<String>,
+    /// Only talk to the OSCQuery app whose name starts with this (e.g. `VRChat-Client-A1B2C3`).
+    /// Defaults to any VRChat client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vrchat_client: Option<String>,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            expose: false,
+            bind: None,
+            vrc_port: 9000,
+            vrc_ip: None,
+            osc_port: 9002,
+            advertise: true,
+            oscquery_url: None,
+            vrchat_osc_dir: None,
+            vrchat_client: None,
+        }
+    }
+}
+
+impl OscConfig {
+    /// The address oscavmgr listens on.
+    pub fn bind_ip(&self) -> anyhow::Result<IpAddr> {
+        let Some(bind) = self.bind.as_deref() else {
+            return Ok(match self.expose {
+                true => Ipv4Addr::UNSPECIFIED.into(),
+                false => Ipv4Addr::LOCALHOST.into(),
+            });
+        };
+        if let Ok(ip) = bind.parse() {
+            return Ok(ip);
+        }
+
+        // an interface: its IPv4 address, or else one that works without a scope id
+        let interfaces = if_addrs::get_if_addrs().context("could not list network interfaces")?;
+        interfaces
+            .iter()
+            .filter(|interface| interface.name == bind)
+            .map(|interface| interface.ip())
+            .filter(|ip| match ip {
+                IpAddr::V4(_) => true,
+                IpAddr::V6(ip) => !ip.is_unicast_link_local(),
+            })
+            .min_by_key(|ip| ip.is_ipv6())
+            .with_context(|| format!("no address or network interface named \"{}\"", bind))
+    }
+
+    /// Where to send OSC until VRChat's OSCQuery server tells. `None` while oscavmgr is
+    /// bound to an address other machines use and nothing says where VRChat is.
+    pub fn vrc_addr(&self, bind: IpAddr) -> Option<SocketAddr> {
+        let localhost = match bind {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+        let ip = self
+            .vrc_ip
+            .or_else(|| self.oscquery_url.as_deref().and_then(url_ip))
+            .or_else(|| (bind.is_loopback() || bind.is_unspecified()).then_some(localhost))?;
+        Some(SocketAddr::new(ip, self.vrc_port))
+    }
+}
+
+/// The IP address in an `http://HOST[:PORT]/...` URL, if its host is one.
+pub fn url_ip(url: &str) -> Option<IpAddr> {
+    let host = url.strip_prefix("http://")?.split('/').next()?;
+    match host.parse::<SocketAddr>() {
+        Ok(addr) => Some(addr.ip()),
+        Err(_) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .ok(),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterConfig {
+    /// Apps that receive a copy of VRChat's OSC output, as `HOST:PORT[=/prefix,...]`
+    pub routes: Vec<RouteTarget>,
+    /// Local port whose traffic is relayed to VRChat
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackingConfig {
+    /// Face parameters are only re-sent once they change by more than this
+    pub send_deadband: f32,
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        Self {
+            send_deadband: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalibrationConfig {
+    /// Remap face shapes using the saved calibration
+    pub enabled: bool,
+    /// Name of the saved calibration, for when several people share a computer
+    pub profile: String,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            profile: "default".into(),
+        }
+    }
+}
+
+/// Smoothing per face region, applied after calibration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Eye look direction, both the gaze angles and the EyeX / EyeY shapes
+    pub gaze: ShapeFilter,
+    pub eyelids: ShapeFilter,
+    pub brows: ShapeFilter,
+    pub mouth: ShapeFilter,
+    pub tongue: ShapeFilter,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ShapeFilter {
+    #[default]
+    None,
+    /// Exponential moving average: simple, but lags behind fast motion as much as it smooths
+    Ema(EmaParams),
+    /// One Euro filter: smooths slow jitter strongly and lets fast motion through
+    OneEuro(OneEuroParams),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmaParams {
+    /// Time to cover 63% of a sudden change
+    pub time_constant_ms: f32,
+}
+
+impl Default for EmaParams {
+    fn default() -> Self {
+        Self {
+            time_constant_ms: 50.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OneEuroParams {
+    /// Cutoff frequency in Hz while still. Lower is smoother.
+    pub min_cutoff: f32,
+    /// How quickly the cutoff rises with speed. Higher lags less.
+    pub beta: f32,
+    /// Cutoff frequency in Hz for the speed estimate
+    pub d_cutoff: f32,
+}
+
+impl Default for OneEuroParams {
+    fn default() -> Self {
+        Self {
+            min_cutoff: 1.,
+            beta: 0.5,
+            d_cutoff: 1.,
+        }
+    }
+}
+
+/// What to do with face regions the tracker stops updating.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackingLossConfig {
+    /// Fade stale regions to neutral
+    pub enabled: bool,
+    /// How long a region may go without data before it counts as lost
+    pub timeout_ms: u64,
+    /// How long fading out (and back in) takes
+    pub fade_ms: u64,
+    /// Blink and glance around while eye tracking is lost, instead of staring ahead
+    pub procedural_eyes: bool,
+}
+
+impl Default for TrackingLossConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout_ms: 1000,
+            fade_ms: 500,
+            procedural_eyes: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormulasConfig {
+    /// Compute the standard combined expressions (EyeLidLeft, SmileSadLeft, ...)
+    pub builtin: bool,
+    /// More formulas, run after the built-in ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl Default for FormulasConfig {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            file: None,
+        }
+    }
+}
+
+/// How eye gaze is sent to VRChat's native eye tracking.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EyeOutputConfig {
+    pub mode: EyeOutputMode,
+    /// Distance in meters for the modes that send one, when only one eye is tracked
+    pub focus_distance_m: f32,
+    /// Modes for specific avatars, by avatar id (avtr_...)
+    pub avatars: BTreeMap<String, EyeOutputMode>,
+}
+
+impl Default for EyeOutputConfig {
+    fn default() -> Self {
+        Self {
+            mode: EyeOutputMode::default(),
+            focus_distance_m: 1.,
+            avatars: BTreeMap::new(),
+        }
+    }
+}
+
+impl EyeOutputConfig {
+    pub fn mode_for(&self, avatar_id: Option<&str>) -> EyeOutputMode {
+        avatar_id
+            .and_then(|id| self.avatars.get(id))
+            .copied()
+            .unwrap_or(self.mode)
+    }
+}
+
+/// The `/tracking/eye/` addresses VRChat accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EyeOutputMode {
+    /// Pitch and yaw of each eye, in degrees
+    #[default]
+    LeftRightPitchYaw,
+    /// A unit gaze vector for each eye
+    LeftRightVec,
+    /// Pitch and yaw of both eyes together
+    CenterPitchYaw,
+    /// Pitch and yaw of both eyes together, plus the focus distance in meters
+    CenterPitchYawDist,
+    /// A unit gaze vector for both eyes together
+    CenterVec,
+    /// A gaze vector as long as the focus distance
+    CenterVecFull,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoPilotConfig {
+    /// Distance in meters before a grabbed seeker starts following
+    pub move_threshold: f32,
+    /// Distance in meters before following at full speed
+    pub run_threshold: f32,
+    /// Angle in degrees before turning towards a stationary target
+    pub rotate_start_threshold_deg: f32,
+}
+
+impl Default for AutoPilotConfig {
+    fn default() -> Self {
+        Self {
+            move_threshold: 0.1,
+            run_threshold: 0.5,
+            rotate_start_threshold_deg: 360.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Interval between sending two stored values
+    pub tick_ms: u64,
+    /// Interval between writes of extMem.json
+    pub save_interval_secs: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            tick_ms: 250,
+            save_interval_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BabbleConfig {
+    /// Babble receiver port, relative to the listen port
+    pub babble_port_offset: u16,
+    /// ETVR receiver port, relative to the listen port
+    pub etvr_port_offset: u16,
+    /// VrcAdvert HTTP port, relative to each receiver port
+    pub http_port_offset: u16,
+}
+
+impl Default for BabbleConfig {
+    fn default() -> Self {
+        Self {
+            babble_port_offset: 10,
+            etvr_port_offset: 20,
+            http_port_offset: 1,
+        }
+    }
+}
+
+impl BabbleConfig {
+    /// The Babble receiver, Babble HTTP, ETVR receiver and ETVR HTTP ports
+    /// for `listen_port`, or `None` if one of them would be past 65535.
+    pub fn ports(&self, listen_port: u16) -> Option<[u16; 4]> {
+        let babble_recv = listen_port.checked_add(self.babble_port_offset)?;
+        let etvr_recv = listen_port.checked_add(self.etvr_port_offset)?;
+        Some([
+            babble_recv,
+            babble_recv.checked_add(self.http_port_offset)?,
+            etvr_recv,
+            etvr_recv.checked_add(self.http_port_offset)?,
+        ])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositorConfig {
+    /// A source that has not sent data for a region for this long is skipped for that region
+    pub stale_after_ms: u64,
+    pub sources: Vec<CompositorSource>,
+    /// Source names in priority order, per region. Empty means all sources in listed order.
+    pub regions: CompositorRegions,
+}
+
+impl Default for CompositorConfig {
+    fn default() -> Self {
+        Self {
+            stale_after_ms: 500,
+            sources: Vec::new(),
+            regions: CompositorRegions::default(),
+        }
+    }
+}
+
+// deny_unknown_fields does not work together with flatten
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompositorSource {
+    pub name: String,
+    #[serde(flatten)]
+    pub face: FaceSetup,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositorRegions {
+    pub eyes: Vec<String>,
+    pub brows: Vec<String>,
+    pub mouth: Vec<String>,
+    pub tongue: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VmcConfig {
+    /// Send face tracking to a VMC protocol receiver (VSeeFace, VNyan, Warudo...)
+    pub enabled: bool,
+    /// Address the VMC receiver listens on
+    pub target: SocketAddr,
+    /// Blendshape names to send: "arkit" (perfect sync) or "vrm"
+    pub preset: VmcPreset,
+    /// Send nothing to VRChat, for when the VMC receiver is the only app in use
+    pub replace_upstream: bool,
+}
+
+impl Default for VmcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 39539),
+            preset: VmcPreset::Arkit,
+            replace_upstream: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VmcPreset {
+    /// The 52 ARKit blendshapes, for perfect sync avatars
+    Arkit,
+    /// The VRM 0.x preset clips: visemes, blinks, emotions and look directions
+    Vrm,
+}
+
+impl Config {
+    pub fn default_path() -> String {
+        format!("{}/{}", CONFIG_DIR.as_ref(), FILE_NAME)
+    }
+
+    pub fn path(args: &Args) -> String {
+        args.config.clone().unwrap_or_else(Self::default_path)
+    }
+
+    /// Reads the config file (if any) and applies the command line on top of it.
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let path = Self::path(args);
+
+        let mut config = match fs::read_to_string(&path) {
+            Ok(text) => {
+                log::info!("Loading config from {}", path);
+                Self::parse(&text).with_context(|| format!("Invalid config file {}", path))?
+            }
+            // only an explicitly requested file is mandatory
+            Err(e) if e.kind() == ErrorKind::NotFound && args.config.is_none() => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Could not read {}", path)),
+        };
+
+        config.apply_args(args);
+        config
+            .validate()
+            .with_context(|| format!("Invalid configuration (file: {})", path))?;
+
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_args(&mut self, args: &Args) {
+        if let Some(Command::Face(face)) = args.command.as_ref() {
+            self.face = face.clone();
+        }
+        if args.avatar.is_some() {
+            self.avatar = args.avatar.clone();
+        }
+        self.osc.expose |= args.expose;
+        if args.bind.is_some() {
+            self.osc.bind = args.bind.clone();
+        }
+        if let Some(port) = args.vrc_port {
+            self.osc.vrc_port = port;
+        }
+        if args.vrc_ip.is_some() {
+            self.osc.vrc_ip = args.vrc_ip;
+        }
+        if let Some(port) = args.osc_port {
+            self.osc.osc_port = port;
+        }
+        if args.vrc_client.is_some() {
+            self.osc.vrchat_client = args.vrc_client.clone();
+        }
+        if !args.routes.is_empty() {
+            self.router.routes = args.routes.clone();
+        }
+        if args.router_port.is_some() {
+            self.router.listen_port = args.router_port;
+        }
+        if let Some(target) = args.vmc {
+            self.vmc.enabled = true;
+            self.vmc.target = target;
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.osc.vrc_port == 0 {
+            bail!("osc.vrc_port must not be 0");
+        }
+        if self.osc.osc_port == 0 {
+            bail!("osc.osc_port must not be 0");
+        }
+        if let Some(url) = self.osc.oscquery_url.as_ref() {
+            if !url.starts_with("http://") {
+                bail!("osc.oscquery_url must start with http://, got \"{}\"", url);
+            }
+        }
+        if let Err(e) = self.osc.bind_ip() {
+            bail!("osc.bind: {e:#}");
+        }
+        if self.osc.vrchat_client.as_deref() == Some("") {
+            bail!("osc.vrchat_client must not be empty");
+        }
+        if self.router.listen_port == Some(0) {
+            bail!("router.listen_port must not be 0");
+        }
+        if !(0.0..1.0).contains(&self.tracking.send_deadband) {
+            bail!(
+                "tracking.send_deadband must be in 0..1, got {}",
+                self.tracking.send_deadband
+            );
+        }
+        if self.calibration.profile.is_empty()
+            || !self
+                .calibration
+                .profile
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!(
+                "calibration.profile may only contain letters, digits, - and _, got \"{}\"",
+                self.calibration.profile
+            );
+        }
+        for (group, filter) in [
+            ("gaze", &self.filter.gaze),
+            ("eyelids", &self.filter.eyelids),
+            ("brows", &self.filter.brows),
+            ("mouth", &self.filter.mouth),
+            ("tongue", &self.filter.tongue),
+        ] {
+            validate_filter(group, filter)?;
+        }
+        if self.tracking_loss.timeout_ms == 0 {
+            bail!("tracking_loss.timeout_ms must not be 0");
+        }
+        if self.eye_output.focus_distance_m.is_nan() || self.eye_output.focus_distance_m <= 0. {
+            bail!(
+                "eye_output.focus_distance_m must be greater than 0, got {}",
+                self.eye_output.focus_distance_m
+            );
+        }
+        for (name, curve) in self.curves.iter() {
+            curve_shape(name)?;
+            curve.validate(name)?;
+        }
+        for (key, value) in [
+            ("autopilot.move_threshold", self.autopilot.move_threshold),
+            ("autopilot.run_threshold", self.autopilot.run_threshold),
+            (
+                "autopilot.rotate_start_threshold_deg",
+                self.autopilot.rotate_start_threshold_deg,
+            ),
+        ] {
+            if value.is_nan() || value <= 0. {
+                bail!("{} must be greater than 0, got {}", key, value);
+            }
+        }
+        if self.storage.tick_ms == 0 {
+            bail!("storage.tick_ms must not be 0");
+        }
+        if self.babble.babble_port_offset == 0 || self.babble.etvr_port_offset == 0 {
+            bail!("babble.babble_port_offset and babble.etvr_port_offset must not be 0");
+        }
+        if self.babble.babble_port_offset == self.babble.etvr_port_offset {
+            bail!("babble.babble_port_offset and babble.etvr_port_offset must differ");
+        }
+        if self.babble.http_port_offset == 0
+            || self.babble.http_port_offset
+                == self
+                    .babble
+                    .etvr_port_offset
+                    .abs_diff(self.babble.babble_port_offset)
+        {
+            bail!("babble.http_port_offset must not collide with a receiver port");
+        }
+        #[cfg(feature = "babble")]
+        for face in
+            std::iter::once(&self.face).chain(self.compositor.sources.iter().map(|s| &s.face))
+        {
+            if let FaceSetup::Babble { listen } = face {
+                if self.babble.ports(*listen).is_none() {
+                    bail!(
+                        "babble port offsets are too large for listen port {}",
+                        listen
+                    );
+                }
+            }
+        }
+        if self.vmc.target.port() == 0 {
+            bail!("vmc.target must have a port");
+        }
+        if self.vmc.replace_upstream && !self.vmc.enabled {
+            bail!("vmc.replace_upstream requires vmc.enabled");
+        }
+        self.validate_compositor()
+    }
+
+    fn validate_compositor(&self) -> anyhow::Result<()> {
+        let compositor = &self.compositor;
+        validate_face("face", &self.face)?;
+        if self.face == FaceSetup::Composite && compositor.sources.is_empty() {
+            bail!("compositor.sources must not be empty when face.provider is \"composite\"");
+        }
+        for (i, source) in compositor.sources.iter().enumerate() {
+            if source.name.is_empty() {
+                bail!("compositor.sources[{}].name must not be empty", i);
+            }
+            if source.face == FaceSetup::Composite {
+                bail!("compositor.sources[{}].provider cannot be \"composite\"", i);
+            }
+            if compositor.sources[..i]
+                .iter()
+                .any(|s| s.name == source.name)
+            {
+                bail!(
+                    "compositor.sources[{}].name: duplicate source \"{}\"",
+                    i,
+                    source.name
+                );
+            }
+        }
+        for (region, names) in [
+            ("eyes", &compositor.regions.eyes),
+            ("brows", &compositor.regions.brows),
+            ("mouth", &compositor.regions.mouth),
+            ("tongue", &compositor.regions.tongue),
+        ] {
+            for name in names.iter() {
+                if !compositor.sources.iter().any(|s| &s.name == name) {
+                    bail!("compositor.regions.{}: unknown source \"{}\"", region, name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The effective configuration, in the same format as the config file.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(&self.to_value()).expect("config is serializable")
+    }
+
+    /// The configured response curves, sampled from 0 to 1, as a text table.
+    pub fn curve_table(&self) -> String {
+        curve_table(&self.curves)
+    }
+
+    /// Lists every key that differs from `other`, as `key: old -> new`.
+    pub fn diff(&self, other: &Config) -> Vec<String> {
+        let mut old = BTreeMap::new();
+        let mut new = BTreeMap::new();
+        flatten("", &self.to_value(), &mut old);
+        flatten("", &other.to_value(), &mut new);
+
+        let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let (before, after) = (old.get(key), new.get(key));
+                (before != after).then(|| {
+                    format!(
+                        "{}: {} -> {}",
+                        key,
+                        before.map_or("(unset)", String::as_str),
+                        after.map_or("(unset)", String::as_str)
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn to_value(&self) -> toml::Value {
+        let mut value = toml::Value::try_from(self).expect("config is serializable");
+        round_floats(&mut value);
+        value
+    }
+}
+
+/// f32 values go through f64 on their way to TOML, which turns 0.01 into 0.009999999776482582.
+fn round_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => *f = (*f * 1e6).round() / 1e6,
+        toml::Value::Array(array) => array.iter_mut().for_each(round_floats),
+        toml::Value::Table(table) => table.values_mut().for_each(round_floats),
+        _ => {}
+    }
+}
+
+fn validate_face(key: &str, face: &FaceSetup) -> anyhow::Result<()> {
+    if let FaceSetup::Replay { speed, .. } = face {
+        if speed.is_nan() || *speed <= 0. {
+            bail!("{}.speed must be greater than 0, got {}", key, speed);
+        }
+    }
+    Ok(())
+}
+
+fn validate_filter(group: &str, filter: &ShapeFilter) -> anyhow::Result<()> {
+    let values = match filter {
+        ShapeFilter::None => vec![],
+        ShapeFilter::Ema(params) => vec![("time_constant_ms", params.time_constant_ms, false)],
+        ShapeFilter::OneEuro(params) => vec![
+            ("min_cutoff", params.min_cutoff, false),
+            ("beta", params.beta, true),
+            ("d_cutoff", params.d_cutoff, false),
+        ],
+    };
+    for (key, value, zero_ok) in values {
+        if value.is_nan() || value < 0. || (value == 0. && !zero_ok) {
+            bail!(
+                "filter.{}.{} must be {} 0, got {}",
+                group,
+                key,
+                if zero_ok { "at least" } else { "greater than" },
+                value
+            );
+        }
+    }
+    Ok(())
+}
+
+fn flatten(prefix: &str, value: &toml::Value, out: &mut BTreeMap<String, String>) {
+    if let toml::Value::Table(table) = value {
+        for (key, value) in table.iter() {
+            let key = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            flatten(&key, value, out);
+        }
+    } else {
+        out.insert(prefix.to_string(), value.to_string());
+    }
+}
+
+/// Polls a file's modification time, so edits can be picked up without a restart.
+pub struct WatchedFile {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            modified: None,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether `changed` has ever seen the file.
+    pub fn seen(&self) -> bool {
+        self.modified.is_some()
+    }
+
+    /// True if the file appeared or was modified since the last call.
+    pub fn changed(&mut self) -> bool {
+        let modified = fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printed_config_parses_back() {
+        let mut config = Config::default();
+        config.router.routes.push("9010=/avatar/".parse().unwrap());
+
+        let text = config.to_toml();
+        assert!(text.contains("send_deadband = 0.01\n"));
+        assert_eq!(Config::parse(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn eye_output_can_differ_per_avatar() {
+        let config = Config::parse(
+            "[eye_output]\nmode = \"center_vec\"\n\n[eye_output.avatars]\navtr_1234 = \"center_pitch_yaw_dist\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.eye_output.mode_for(None), EyeOutputMode::CenterVec);
+        assert_eq!(
+            config.eye_output.mode_for(Some("avtr_1234")),
+            EyeOutputMode::CenterPitchYawDist
+        );
+        assert_eq!(
+            config.eye_output.mode_for(Some("avtr_other")),
+            EyeOutputMode::CenterVec
+        );
+    }
+
+    #[test]
+    fn errors_name_the_bad_key() {
+        let unknown = Config::parse("[autopilot]\nmove_treshold = 0.2\n").unwrap_err();
+        assert!(format!("{unknown:#}").contains("move_treshold"));
+
+        let invalid = Config::parse("[tracking]\nsend_deadband = 2.0\n").unwrap_err();
+        assert!(format!("{invalid:#}").contains("tracking.send_deadband"));
+    }
+
+    #[cfg(feature = "babble")]
+    #[test]
+    fn babble_ports_must_fit() {
+        let face = "[face]\nprovider = \"babble\"\nlisten = ";
+        assert!(Config::parse(&format!("{face}65514\n")).is_ok());
+        let invalid = Config::parse(&format!("{face}65530\n")).unwrap_err();
+        assert!(format!("{invalid:#}").contains("65530"));
+    }
+
+    #[test]
+    fn filters_fill_in_defaults() {
+        let config = Config::parse("[filter.mouth]\nkind = \"one_euro\"\nbeta = 2.0\n").unwrap();
+        assert_eq!(
+            config.filter.mouth,
+            ShapeFilter::OneEuro(OneEuroParams {
+                beta: 2.,
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.filter.gaze, ShapeFilter::None);
+
+        let invalid =
+            Config::parse("[filter.gaze]\nkind = \"ema\"\ntime_constant_ms = 0\n").unwrap_err();
+        assert!(format!("{invalid:#}").contains("filter.gaze.time_constant_ms"));
+    }
+
+    #[test]
+    fn curves_are_keyed_by_shape() {
+        let config = Config::parse("[curves.CheekPuffLeft]\ndeadzone = 0.1\n").unwrap();
+        assert_eq!(config.curves["CheekPuffLeft"].deadzone, 0.1);
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+
+        let unknown = Config::parse("[curves.CheekPuf]\ngain = 2.0\n").unwrap_err();
+        assert!(format!("{unknown:#}").contains("curves.CheekPuf"));
+    }
+
+    #[test]
+    fn diff_lists_changed_keys() {
+        let old = Config::default();
+        let new =
+            Config::parse("[storage]\ntick_ms = 100\n[router]\nlisten_port = 9001\n").unwrap();
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                "router.listen_port: (unset) -> 9001".to_string(),
+                "storage.tick_ms: 250 -> 100".to_string(),
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn args_take_precedence() {
+        let mut config = Config::parse("[osc]\nvrc_port = 9100\nosc_port = 9102\n").unwrap();
+        config.apply_args(&Args {
+            osc_port: Some(9202),
+            vrc_client: Some("Tester".into()),
+            ..Default::default()
+        });
+
+        assert_eq!(config.osc.vrc_port, 9100);
+        assert_eq!(config.osc.osc_port, 9202);
+        assert_eq!(config.osc.vrchat_client.as_deref(), Some("Tester"));
+    }
+
+    #[test]
+    fn bind_takes_addresses_or_interfaces() {
+        let mut config = Config::default();
+        assert_eq!(config.osc.bind_ip().unwrap(), Ipv4Addr::LOCALHOST);
+        config.osc.expose = true;
+        assert_eq!(config.osc.bind_ip().unwrap(), Ipv4Addr::UNSPECIFIED);
+
+        let config = Config::parse("[osc]\nexpose = true\nbind = \"::1\"\n").unwrap();
+        assert_eq!(
+            config.osc.bind_ip().unwrap(),
+            "::1".parse::<IpAddr>().unwrap()
+        );
+
+        let loopback = if_addrs::get_if_addrs()
+            .unwrap()
+            .into_iter()
+            .find(|interface| interface.ip() == Ipv4Addr::LOCALHOST);
+        if let Some(loopback) = loopback {
+            let text = format!("[osc]\nbind = \"{}\"\n", loopback.name);
+            let config = Config::parse(&text).unwrap();
+            assert_eq!(config.osc.bind_ip().unwrap(), Ipv4Addr::LOCALHOST);
+        }
+
+        let err = Config::parse("[osc]\nbind = \"no-such-nic0\"\n").unwrap_err();
+        assert!(format!("{err:#}").starts_with("osc.bind: "), "{err:#}");
+    }
+
+    #[test]
+    fn never_sends_to_our_own_bind_address() {
+        let lan: IpAddr = "192.0.2.20".parse().unwrap();
+        let mut osc = OscConfig::default();
+        assert_eq!(osc.vrc_addr(lan), None);
+        assert_eq!(
+            osc.vrc_addr(Ipv4Addr::LOCALHOST.into()),
+            Some(SocketAddr::from(([127, 0, 0, 1], 9000)))
+        );
+        assert_eq!(
+            osc.vrc_addr(Ipv6Addr::UNSPECIFIED.into()),
+            Some(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 9000))
+        );
+
+        osc.oscquery_url = Some("http://192.0.2.30:9001".into());
+        assert_eq!(
+            osc.vrc_addr(lan),
+            Some(SocketAddr::from(([192, 0, 2, 30], 9000)))
+        );
+        osc.vrc_ip = Some("192.0.2.40".parse().unwrap());
+        assert_eq!(
+            osc.vrc_addr(lan),
+            Some(SocketAddr::from(([192, 0, 2, 40], 9000)))
+        );
+
+        assert_eq!(
+            url_ip("http://[fd00::1]:9001/avatar"),
+            "fd00::1".parse().ok()
+        );
+        assert_eq!(url_ip("http://192.0.2.30"), "192.0.2.30".parse().ok());
+        assert_eq!(url_ip("http://vrchat.local:9001"), None);
+    }
+}
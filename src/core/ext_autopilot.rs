@@ -8,12 +8,9 @@ use rosc::{OscBundle, OscType};
 
 use crate::core::ext_tracking::unified::UnifiedExpressions;
 
-use super::{bundle::AvatarBundle, ext_tracking::ExtTracking, AppState};
+use super::{bundle::AvatarBundle, config::AutoPilotConfig, ext_tracking::ExtTracking, AppState};
 
-const MOVE_THRESHOLD_METERS: f32 = 0.1;
-const RUN_THRESHOLD_METERS: f32 = 0.5;
 const ROTATE_THRESHOLD_RAD: f32 = PI / 120.; // 1.5deg
-const ROTATE_START_THRESHOLD_RAD: f32 = PI * 2.; // never
 
 static STA_FLW: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "FOLLOW".color(Color::Green)).into());
 static STA_MAN: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "MANUAL".color(Color::Green)).into());
@@ -21,6 +18,9 @@ static STA_OFF: Lazy<Arc<str>> =
     Lazy::new(|| format!("{}", "AP-OFF".color(Color::BrightBlack)).into());
 
 pub struct ExtAutoPilot {
+    move_threshold: f32,
+    run_threshold: f32,
+    rotate_start_threshold: f32,
     voice: bool,
     voice_lock: bool,
     jumped: bool,
@@ -30,15 +30,26 @@ pub struct ExtAutoPilot {
 }
 
 impl ExtAutoPilot {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(config: &AutoPilotConfig) -> Self {
+        let mut me = Self {
+            move_threshold: 0.,
+            run_threshold: 0.,
+            rotate_start_threshold: 0.,
             voice: false,
             voice_lock: false,
             jumped: false,
             jump_cd: 0,
             follow_before: false,
             last_sent: Vec3::ZERO,
-        }
+        };
+        me.reconfigure(config);
+        me
+    }
+
+    pub fn reconfigure(&mut self, config: &AutoPilotConfig) {
+        self.move_threshold = config.move_threshold;
+        self.run_threshold = config.run_threshold;
+        self.rotate_start_threshold = config.rotate_start_threshold_deg.to_radians();
     }
 
     pub fn step(&mut self, state: &mut AppState, tracking: &ExtTracking, bundle: &mut OscBundle) {
@@ -47,7 +58,7 @@ impl ExtAutoPilot {
         self.avatar_flight(state, bundle);
 
         let mut follow = false;
-        let mut follow_distance = MOVE_THRESHOLD_METERS;
+        let mut follow_distance = self.move_threshold;
         let mut allow_rotate = false;
 
         if let Some(OscType::Bool(true)) = state.params.get("Seeker_IsGrabbed") {
@@ -55,7 +66,7 @@ impl ExtAutoPilot {
         } else if let Some(OscType::Bool(true)) = state.params.get("Tracker1_Enable") {
             follow = true;
             allow_rotate = true;
-            follow_distance = RUN_THRESHOLD_METERS;
+            follow_distance = self.run_threshold;
         }
 
         let mut look_horizontal = 0.;
@@ -74,7 +85,7 @@ impl ExtAutoPilot {
                 let abs_theta = theta.abs();
 
                 if dist_horizontal > follow_distance {
-                    let mult = (dist_horizontal / RUN_THRESHOLD_METERS).clamp(0., 1.);
+                    let mult = (dist_horizontal / self.run_threshold).clamp(0., 1.);
 
                     vertical = tgt.z / dist_horizontal * mult;
                     horizontal = tgt.x / dist_horizontal * mult;
@@ -82,7 +93,7 @@ impl ExtAutoPilot {
                         look_horizontal = theta.signum() * (abs_theta / (PI / 2.)).clamp(0., 1.);
                     }
                     self.follow_before = true;
-                } else if allow_rotate && abs_theta > ROTATE_START_THRESHOLD_RAD {
+                } else if allow_rotate && abs_theta > self.rotate_start_threshold {
                     look_horizontal = theta.signum() * (abs_theta / (PI / 2.)).clamp(0., 1.);
                 }
                 state.status.add_item(STA_FLW.clone());
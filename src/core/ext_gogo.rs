@@ -5,7 +5,6 @@ use rosc::{OscBundle, OscType};
 use serde::{Deserialize, Serialize};
 
 use super::bundle::AvatarBundle;
-use super::folders::CONFIG_DIR;
 use super::AvatarParameters;
 
 const FILE_NAME: &str = "extGogo.json";
@@ -37,8 +36,8 @@ pub struct ExtGogo {
 }
 
 impl ExtGogo {
-    pub fn new() -> ExtGogo {
-        let path = format!("{}/{}", CONFIG_DIR.as_ref(), FILE_NAME);
+    pub fn new(config_dir: &str) -> ExtGogo {
+        let path = format!("{}/{}", config_dir, FILE_NAME);
 
         let mut me = File::open(&path)
             .ok()
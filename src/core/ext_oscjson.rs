@@ -1,118 +1,153 @@
+use anyhow::Context;
 use log::{debug, info, warn};
-use mdns_sd::{ServiceDaemon, ServiceEvent};
 use rosc::{OscBundle, OscType};
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{Read, Write},
-    sync::Arc,
-    thread,
-    time::Duration,
-};
+use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc};
 
-use super::{bundle::AvatarBundle, folders::CONFIG_DIR};
+use super::{
+    avatar_files::{is_avatar_id, AvatarFiles},
+    bundle::AvatarBundle,
+    config::OscConfig,
+    folders::OSC_DIR,
+    oscjson_worker::{OscJsonWorker, WorkerEvent},
+};
 
 pub struct ExtOscJson {
-    mdns: ServiceDaemon,
-    mdns_recv: mdns_sd::Receiver<ServiceEvent>,
-    oscjson_addr: Option<Arc<str>>,
-    next_run: std::time::Instant,
-    client: reqwest::blocking::Client,
+    worker: OscJsonWorker,
+    /// Whether VRChat's OSCQuery server has been found
+    found: bool,
+    avatar_files: AvatarFiles,
+    /// The last id VRChat sent on `/avatar/change`
+    current_avatar: Option<String>,
+    /// The tree in use for the current avatar
+    in_use: Option<OscJsonNode>,
+    /// Where avatar trees are cached
+    config_dir: Arc<str>,
 }
 
-impl ExtOscJson {
-    pub fn new() -> Self {
-        let mdns = ServiceDaemon::new().unwrap();
-        let mdns_recv = mdns.browse("_oscjson._tcp.local.").unwrap();
-        let client = reqwest::blocking::Client::new();
+pub enum OscJsonEvent {
+    /// VRChat was found: look the avatar up
+    Lookup,
+    /// A newer tree for the current avatar than the one in use
+    Refreshed(OscJsonNode),
+    /// VRChat takes OSC at this address
+    Upstream(SocketAddr),
+}
 
+impl ExtOscJson {
+    pub fn new(config: &OscConfig, config_dir: Arc<str>) -> Self {
         Self {
-            mdns,
-            mdns_recv,
-            oscjson_addr: None,
-            next_run: std::time::Instant::now(),
-            client,
+            worker: OscJsonWorker::start(
+                config.oscquery_url.as_deref(),
+                config.vrchat_client.as_deref(),
+                config_dir.clone(),
+            ),
+            found: false,
+            avatar_files: AvatarFiles::new(config.vrchat_osc_dir.as_deref().unwrap_or(&OSC_DIR)),
+            current_avatar: None,
+            in_use: None,
+            config_dir,
         }
     }
 
-    pub fn step(&mut self) -> bool {
-        let mut notify_avatar = false;
-        if self.next_run > std::time::Instant::now() {
-            return notify_avatar;
-        }
-        self.next_run = std::time::Instant::now() + std::time::Duration::from_secs(15);
-
-        for event in self.mdns_recv.try_iter() {
-            if let ServiceEvent::ServiceResolved(info) = event {
-                if !info.get_fullname().starts_with("VRChat-Client-") {
-                    continue;
+    pub fn step(&mut self) -> Option<OscJsonEvent> {
+        while let Some(event) = self.worker.try_recv() {
+            match event {
+                WorkerEvent::Found => {
+                    self.found = true;
+                    return Some(OscJsonEvent::Lookup);
                 }
-                let addr = info.get_addresses().iter().next().unwrap();
-                info!(
-                    "Found OSCJSON service: {} @ {}:{}",
-                    info.get_fullname(),
-                    addr,
-                    info.get_port()
-                );
-
-                if self.oscjson_addr.is_none() {
-                    notify_avatar = true;
+                WorkerEvent::Upstream(addr) => return Some(OscJsonEvent::Upstream(addr)),
+                WorkerEvent::Fetched { avatar_id, node } => {
+                    if let Some(node) = self.replace_in_use(avatar_id.as_deref(), node) {
+                        return Some(OscJsonEvent::Refreshed(node));
+                    }
                 }
-
-                self.oscjson_addr =
-                    Some(format!("http://{}:{}/avatar", addr, info.get_port()).into());
             }
         }
 
-        if self.oscjson_addr.is_some() && notify_avatar {
-            self.avatar(&AvatarIdentifier::Default);
+        // VRChat's own files only matter while OSCQuery can't be reached
+        let changed = self.avatar_files.scan();
+        let id = self.current_avatar.clone()?;
+        if self.found || !changed.contains(&id) {
+            return None;
         }
-        notify_avatar
+        info!("VRChat updated the OSC config of the current avatar");
+        let node = self.avatar_files.load(&id)?;
+        self.replace_in_use(Some(&id), node)
+            .map(OscJsonEvent::Refreshed)
     }
 
-    pub fn avatar(&mut self, avatar: &AvatarIdentifier) -> Option<OscJsonNode> {
-        let mut json = String::new();
+    fn replace_in_use(
+        &mut self,
+        avatar_id: Option<&str>,
+        node: OscJsonNode,
+    ) -> Option<OscJsonNode> {
+        if self.current_avatar.as_deref() != avatar_id || self.in_use.as_ref() == Some(&node) {
+            return None;
+        }
+        if self.in_use.is_some() {
+            info!("Avatar parameters changed since they were cached, updating");
+        }
+        self.in_use = Some(node.clone());
+        Some(node)
+    }
 
+    /// Returns the avatar's tree right away if it is cached or VRChat has a file for it.
+    /// Otherwise, the tree comes later from `step`.
+    pub fn avatar(&mut self, avatar: &AvatarIdentifier) -> Option<OscJsonNode> {
         if let AvatarIdentifier::Path(path) = avatar {
-            if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut json)) {
-                log::error!("Could not read file: {:?}", e);
-                return None;
-            }
-        } else {
-            let Some(addr) = self.oscjson_addr.as_ref() else {
-                warn!("No avatar oscjson address.");
-                return None;
-            };
+            return fs::read_to_string(path)
+                .context("Could not read file")
+                .and_then(|json| parse_json(&json))
+                .map_err(|e| log::error!("{}: {e:#}", path))
+                .ok();
+        }
 
-            thread::sleep(Duration::from_millis(250));
+        if let AvatarIdentifier::Uid(id) = avatar {
+            self.current_avatar = Some(id.clone());
+        }
+        if !self.found {
+            warn!("No avatar oscjson address yet.");
+        }
+        self.worker.fetch(self.current_avatar.clone());
 
-            let Ok(resp) = self.client.get(addr.as_ref()).send() else {
-                warn!("Failed to send avatar json request.");
-                return None;
-            };
+        let id = self.current_avatar.as_deref()?;
+        self.in_use = match load_cached(&self.config_dir, id) {
+            Some(cached) => {
+                info!("Using cached avatar parameters");
+                Some(cached)
+            }
+            None => self.avatar_files.load(id),
+        };
+        self.in_use.clone()
+    }
+}
 
-            let Ok(text) = resp.text() else {
-                warn!("No payload in avatar json response.");
-                return None;
-            };
+pub fn parse_json(json: &str) -> anyhow::Result<OscJsonNode> {
+    serde_json::from_str(json).map_err(|e| {
+        debug!("{}", json);
+        anyhow::anyhow!("Failed to deserialize avatar json: {}", e)
+    })
+}
 
-            json = text;
-        }
+fn cache_path(config_dir: &str, avatar_id: &str) -> Option<String> {
+    is_avatar_id(avatar_id).then(|| format!("{}/oscavmgr-avatars/{}.json", config_dir, avatar_id))
+}
 
-        let path = format!("{}/{}", CONFIG_DIR.as_ref(), "oscavmgr-avatar.json");
-        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(json.as_bytes())) {
-            warn!("Could not write avatar json file: {:?}", e);
-        }
+fn load_cached(config_dir: &str, avatar_id: &str) -> Option<OscJsonNode> {
+    let json = fs::read_to_string(cache_path(config_dir, avatar_id)?).ok()?;
+    serde_json::from_str(&json).ok()
+}
 
-        match serde_json::from_str(&json) {
-            Ok(root_node) => Some(root_node),
-            Err(e) => {
-                warn!("Failed to deserialize avatar json: {}\n", e);
-                debug!("{}", json);
-                None
-            }
-        }
+pub fn store_cached(config_dir: &str, avatar_id: &str, node: &OscJsonNode) {
+    let Some(path) = cache_path(config_dir, avatar_id) else {
+        return;
+    };
+    let result = fs::create_dir_all(format!("{}/oscavmgr-avatars", config_dir))
+        .and_then(|_| fs::write(&path, serde_json::to_string(node).unwrap_or_default()));
+    if let Err(e) = result {
+        warn!("Could not cache avatar parameters at {}: {:?}", path, e);
     }
 }
 
@@ -123,7 +158,7 @@ pub enum AvatarIdentifier {
     Path(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OscJsonNode {
     #[serde(alias = "FULL_PATH")]
     pub full_path: Arc<str>,
@@ -155,6 +190,23 @@ impl OscJsonNode {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::harness::{avatar_json, config_dir};
+
+    #[test]
+    fn avatar_trees_are_cached_by_id() {
+        let dir = config_dir();
+        let node = parse_json(&avatar_json(&[("VSync", "T"), ("FT/v2/JawOpen", "f")])).unwrap();
+
+        assert_eq!(load_cached(&dir, "avtr_cached"), None);
+        store_cached(&dir, "avtr_cached", &node);
+        assert_eq!(load_cached(&dir, "avtr_cached"), Some(node));
+        assert_eq!(cache_path(&dir, "../avtr_cached"), None);
+    }
+}
+
 #[derive(Clone)]
 pub struct MysteryParam {
     pub name: Arc<str>,
@@ -167,9 +219,9 @@ pub struct MysteryParam {
 }
 
 impl MysteryParam {
-    pub fn send(&mut self, value: f32, bundle: &mut OscBundle) {
+    pub fn send(&mut self, value: f32, deadband: f32, bundle: &mut OscBundle) {
         if let Some(addr) = self.main_address.as_ref() {
-            if (value - self.last_value).abs() > 0.01 {
+            if (value - self.last_value).abs() > deadband {
                 bundle.send_parameter(addr, OscType::Float(value));
                 self.last_value = value;
             }
@@ -1,8 +1,11 @@
-use std::{fs::File, time::Instant};
+use std::{
+    fs::File,
+    time::{Duration, Instant},
+};
 
 use rosc::{OscBundle, OscType};
 
-use super::{bundle::AvatarBundle, folders::CONFIG_DIR};
+use super::{bundle::AvatarBundle, config::StorageConfig};
 
 const FILE_NAME: &str = "extMem.json";
 const LENGTH: usize = 255;
@@ -15,11 +18,13 @@ pub struct ExtStorage {
     int_index: usize,
     last_save: Instant,
     last_tick: Instant,
+    tick_interval: Duration,
+    save_interval: Duration,
 }
 
 impl ExtStorage {
-    pub fn new() -> ExtStorage {
-        let path = format!("{}/{}", CONFIG_DIR.as_ref(), FILE_NAME);
+    pub fn new(config: &StorageConfig, config_dir: &str) -> ExtStorage {
+        let path = format!("{}/{}", config_dir, FILE_NAME);
 
         let data: Vec<f32> = File::open(&path)
             .ok()
@@ -35,9 +40,16 @@ impl ExtStorage {
             last_save: Instant::now(),
             last_tick: Instant::now(),
             int_index: 0,
+            tick_interval: Duration::from_millis(config.tick_ms),
+            save_interval: Duration::from_secs(config.save_interval_secs),
         }
     }
 
+    pub fn reconfigure(&mut self, config: &StorageConfig) {
+        self.tick_interval = Duration::from_millis(config.tick_ms);
+        self.save_interval = Duration::from_secs(config.save_interval_secs);
+    }
+
     fn save(&mut self) {
         self.last_save = Instant::now();
         log::info!("Saving ExtStorage to {}", &self.path);
@@ -85,11 +97,7 @@ impl ExtStorage {
     }
 
     pub fn step(&mut self, bundle: &mut OscBundle) {
-        if Instant::now()
-            .saturating_duration_since(self.last_tick)
-            .as_millis()
-            < 250
-        {
+        if Instant::now().saturating_duration_since(self.last_tick) < self.tick_interval {
             return;
         }
         if self.ext_index != 0 {
@@ -106,11 +114,7 @@ impl ExtStorage {
             bundle.send_parameter("IntValue", OscType::Float(value));
         }
 
-        if Instant::now()
-            .saturating_duration_since(self.last_save)
-            .as_secs()
-            > 300
-        {
+        if Instant::now().saturating_duration_since(self.last_save) > self.save_interval {
             self.save();
         }
     }
@@ -17,7 +17,7 @@ use glam::Vec3;
 use once_cell::sync::Lazy;
 use strum::EnumCount;
 use sysinfo::ProcessesToUpdate;
-use websocket::{header::Headers, message::OwnedMessage, ClientBuilder, Message};
+use websocket::{header::Headers, message::OwnedMessage, ClientBuilder, Message, WebSocketError};
 
 use crate::core::{
     ext_tracking::face2_fb::face2_fb_to_unified, AppState, INSTRUCTIONS_END, INSTRUCTIONS_START,
@@ -25,8 +25,10 @@ use crate::core::{
 };
 
 use super::{
+    is_timeout,
+    region::{Region, RegionTimes},
     unified::{UnifiedExpressions, UnifiedTrackingData, NUM_SHAPES},
-    FaceReceiver,
+    FaceReceiver, LoopGuard, LoopHandle, LOOP_POLL,
 };
 
 static STA_ON: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "ALVR".color(Color::Green)).into());
@@ -45,7 +47,8 @@ impl AlvrTrackingData {}
 pub(super) struct AlvrReceiver {
     sender: SyncSender<Box<AlvrTrackingData>>,
     receiver: Receiver<Box<AlvrTrackingData>>,
-    last_received: Instant,
+    last_received: Option<Instant>,
+    guard: LoopGuard,
 }
 
 impl AlvrReceiver {
@@ -54,7 +57,8 @@ impl AlvrReceiver {
         Self {
             sender,
             receiver,
-            last_received: Instant::now(),
+            last_received: None,
+            guard: LoopGuard::default(),
         }
     }
 }
@@ -92,8 +96,9 @@ impl FaceReceiver for AlvrReceiver {
         log::info!("");
         log::info!("{}", *INSTRUCTIONS_END);
         let sender = self.sender.clone();
+        let handle = self.guard.handle();
         thread::spawn(move || {
-            alvr_receive(sender);
+            alvr_receive(sender, handle);
         });
     }
 
@@ -108,7 +113,7 @@ impl FaceReceiver for AlvrReceiver {
             if let Some(new_shapes) = new_data.shapes {
                 data.shapes[..=UnifiedExpressions::COUNT]
                     .copy_from_slice(&new_shapes[..=UnifiedExpressions::COUNT]);
-                self.last_received = Instant::now();
+                self.last_received = Some(Instant::now());
             }
 
             if let Some(head) = new_data.head {
@@ -125,12 +130,19 @@ impl FaceReceiver for AlvrReceiver {
             }
         }
 
-        if self.last_received.elapsed() < Duration::from_secs(1) {
+        if self
+            .last_received
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
             state.status.add_item(STA_ON.clone());
         } else {
             state.status.add_item(STA_OFF.clone());
         }
     }
+
+    fn last_update(&self) -> RegionTimes {
+        [self.last_received; Region::COUNT]
+    }
 }
 
 #[inline(always)]
@@ -153,24 +165,37 @@ const VR_PROCESSES: [&str; 6] = [
     "vrstartup",
 ];
 
-fn alvr_receive(mut sender: SyncSender<Box<AlvrTrackingData>>) {
+fn alvr_receive(mut sender: SyncSender<Box<AlvrTrackingData>>, handle: LoopHandle) {
     let mut system = sysinfo::System::new();
-    loop {
-        match receive_until_err(&mut sender, &mut system) {
+    while !handle.is_stopped() {
+        match receive_until_err(&mut sender, &mut system, &handle) {
             Ok(_) => {
-                thread::sleep(Duration::from_millis(20000));
+                sleep_unless_stopped(&handle, Duration::from_millis(20000));
             }
             Err(e) => {
                 log::warn!("WebSocket error: {}", e);
-                thread::sleep(Duration::from_millis(5000));
+                sleep_unless_stopped(&handle, Duration::from_millis(5000));
             }
         }
     }
 }
 
+/// Sleeps for `duration`, or until the loop is stopped, whichever comes first.
+fn sleep_unless_stopped(handle: &LoopHandle, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !handle.is_stopped() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        thread::sleep(left.min(LOOP_POLL));
+    }
+}
+
 fn receive_until_err(
     sender: &mut SyncSender<Box<AlvrTrackingData>>,
     system: &mut sysinfo::System,
+    handle: &LoopHandle,
 ) -> anyhow::Result<()> {
     const WS_URL: &str = "ws://127.0.0.1:8082/api/events";
     let mut headers = Headers::new();
@@ -180,7 +205,20 @@ fn receive_until_err(
         return Ok(()); // long retry
     };
 
-    while let Ok(message) = ws.recv_message() {
+    // wake up now and then to notice being stopped while ALVR is quiet
+    if let Err(e) = ws.stream_ref().set_read_timeout(Some(LOOP_POLL)) {
+        log::warn!("ALVR: could not set a read timeout: {}", e);
+    }
+
+    loop {
+        if handle.is_stopped() {
+            return Ok(());
+        }
+        let message = match ws.recv_message() {
+            Ok(message) => message,
+            Err(WebSocketError::IoError(e)) if is_timeout(&e) => continue,
+            Err(e) => bail!("connection lost: {}", e),
+        };
         match message {
             OwnedMessage::Close(_) => {
                 let _ = ws.send_message(&Message::close());
@@ -232,8 +270,6 @@ fn receive_until_err(
             _ => {}
         }
     }
-
-    bail!("connection lost");
 }
 
 fn load_devices(
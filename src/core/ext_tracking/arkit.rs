@@ -0,0 +1,340 @@
+use strum::{EnumCount, EnumIter, EnumString, IntoStaticStr};
+
+use super::unified::{
+    UnifiedExpressions, UnifiedShapeAccessors, UnifiedShapes, UnifiedTrackingData, NUM_SHAPES,
+};
+
+/// How far the eyes can turn before ARKit's eye look shapes reach 1.
+const EYE_RANGE_RAD: f32 = std::f32::consts::PI / 6.;
+
+/// Live Link sends its head and eye angles as -1..1, for -90°..90°.
+pub const LIVE_LINK_ANGLE_RAD: f32 = std::f32::consts::FRAC_PI_2;
+
+/// The 52 ARKit blendshapes in the order Live Link Face sends them, followed by
+/// the head and eye angles it appends. The names match the VRM "perfect sync" clips.
+#[allow(unused)]
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, EnumCount, EnumString, IntoStaticStr)]
+#[strum(ascii_case_insensitive)]
+pub enum ArkitShape {
+    EyeBlinkLeft,
+    EyeLookDownLeft,
+    EyeLookInLeft,
+    EyeLookOutLeft,
+    EyeLookUpLeft,
+    EyeSquintLeft,
+    EyeWideLeft,
+    EyeBlinkRight,
+    EyeLookDownRight,
+    EyeLookInRight,
+    EyeLookOutRight,
+    EyeLookUpRight,
+    EyeSquintRight,
+    EyeWideRight,
+    JawForward,
+    JawRight,
+    JawLeft,
+    JawOpen,
+    MouthClose,
+    MouthFunnel,
+    MouthPucker,
+    MouthRight,
+    MouthLeft,
+    MouthSmileLeft,
+    MouthSmileRight,
+    MouthFrownLeft,
+    MouthFrownRight,
+    MouthDimpleLeft,
+    MouthDimpleRight,
+    MouthStretchLeft,
+    MouthStretchRight,
+    MouthRollLower,
+    MouthRollUpper,
+    MouthShrugLower,
+    MouthShrugUpper,
+    MouthPressLeft,
+    MouthPressRight,
+    MouthLowerDownLeft,
+    MouthLowerDownRight,
+    MouthUpperUpLeft,
+    MouthUpperUpRight,
+    BrowDownLeft,
+    BrowDownRight,
+    BrowInnerUp,
+    BrowOuterUpLeft,
+    BrowOuterUpRight,
+    CheekPuff,
+    CheekSquintLeft,
+    CheekSquintRight,
+    NoseSneerLeft,
+    NoseSneerRight,
+    TongueOut,
+
+    // Live Link only, see LIVE_LINK_ANGLE_RAD.
+    // Positive yaw and pitch turn left and up.
+    HeadYaw,
+    HeadPitch,
+    HeadRoll,
+    LeftEyeYaw,
+    LeftEyePitch,
+    LeftEyeRoll,
+    RightEyeYaw,
+    RightEyePitch,
+    RightEyeRoll,
+}
+
+pub const NUM_ARKIT_BLENDSHAPES: usize = ArkitShape::TongueOut as usize + 1;
+
+pub type ArkitShapes = [f32; ArkitShape::COUNT];
+
+/// Gaze of one eye as (pitch, yaw) in radians, positive meaning up and left.
+///
+/// Falls back to the EyeX/EyeY shapes for providers that only send those.
+pub fn eye_gaze(data: &UnifiedTrackingData, eye: usize) -> (f32, f32) {
+    if let Some(euler) = data.eyes[eye].or(data.eyes[0]) {
+        return (euler.x, euler.y);
+    }
+    let x = if eye == 0 {
+        data.getu(UnifiedExpressions::EyeLeftX)
+    } else {
+        data.getu(UnifiedExpressions::EyeRightX)
+    };
+    (
+        data.getu(UnifiedExpressions::EyeY) * EYE_RANGE_RAD,
+        -x * EYE_RANGE_RAD,
+    )
+}
+
+pub fn unified_to_arkit(data: &UnifiedTrackingData) -> ArkitShapes {
+    use ArkitShape as A;
+    use UnifiedExpressions as U;
+
+    let u = |e: U| data.getu(e);
+    let avg = |a: U, b: U| (u(a) + u(b)) * 0.5;
+
+    let mut out = [0f32; ArkitShape::COUNT];
+    let mut set = |shape: A, value: f32| out[shape as usize] = value;
+
+    let (left_pitch, left_yaw) = eye_gaze(data, 0);
+    let (right_pitch, right_yaw) = eye_gaze(data, 1);
+    let look = |angle: f32| (angle / EYE_RANGE_RAD).clamp(0., 1.);
+
+    set(A::EyeBlinkLeft, u(U::EyeClosedLeft));
+    set(A::EyeLookDownLeft, look(-left_pitch));
+    set(A::EyeLookInLeft, look(-left_yaw));
+    set(A::EyeLookOutLeft, look(left_yaw));
+    set(A::EyeLookUpLeft, look(left_pitch));
+    set(A::EyeSquintLeft, u(U::EyeSquintLeft));
+    set(A::EyeWideLeft, u(U::EyeWideLeft));
+    set(A::EyeBlinkRight, u(U::EyeClosedRight));
+    set(A::EyeLookDownRight, look(-right_pitch));
+    set(A::EyeLookInRight, look(right_yaw));
+    set(A::EyeLookOutRight, look(-right_yaw));
+    set(A::EyeLookUpRight, look(right_pitch));
+    set(A::EyeSquintRight, u(U::EyeSquintRight));
+    set(A::EyeWideRight, u(U::EyeWideRight));
+
+    set(A::JawForward, u(U::JawForward));
+    set(A::JawRight, u(U::JawRight));
+    set(A::JawLeft, u(U::JawLeft));
+    set(A::JawOpen, u(U::JawOpen));
+    set(A::MouthClose, u(U::MouthClosed));
+    set(
+        A::MouthFunnel,
+        (avg(U::LipFunnelUpperLeft, U::LipFunnelUpperRight)
+            + avg(U::LipFunnelLowerLeft, U::LipFunnelLowerRight))
+            * 0.5,
+    );
+    set(
+        A::MouthPucker,
+        (avg(U::LipPuckerUpperLeft, U::LipPuckerUpperRight)
+            + avg(U::LipPuckerLowerLeft, U::LipPuckerLowerRight))
+            * 0.5,
+    );
+    set(A::MouthRight, avg(U::MouthUpperRight, U::MouthLowerRight));
+    set(A::MouthLeft, avg(U::MouthUpperLeft, U::MouthLowerLeft));
+    set(A::MouthSmileLeft, u(U::MouthCornerPullLeft));
+    set(A::MouthSmileRight, u(U::MouthCornerPullRight));
+    set(A::MouthFrownLeft, u(U::MouthFrownLeft));
+    set(A::MouthFrownRight, u(U::MouthFrownRight));
+    set(A::MouthDimpleLeft, u(U::MouthDimpleLeft));
+    set(A::MouthDimpleRight, u(U::MouthDimpleRight));
+    set(A::MouthStretchLeft, u(U::MouthStretchLeft));
+    set(A::MouthStretchRight, u(U::MouthStretchRight));
+    set(
+        A::MouthRollLower,
+        avg(U::LipSuckLowerLeft, U::LipSuckLowerRight),
+    );
+    set(
+        A::MouthRollUpper,
+        avg(U::LipSuckUpperLeft, U::LipSuckUpperRight),
+    );
+    set(A::MouthShrugLower, u(U::MouthRaiserLower));
+    set(A::MouthShrugUpper, u(U::MouthRaiserUpper));
+    set(A::MouthPressLeft, u(U::MouthPressLeft));
+    set(A::MouthPressRight, u(U::MouthPressRight));
+    set(A::MouthLowerDownLeft, u(U::MouthLowerDownLeft));
+    set(A::MouthLowerDownRight, u(U::MouthLowerDownRight));
+    set(A::MouthUpperUpLeft, u(U::MouthUpperUpLeft));
+    set(A::MouthUpperUpRight, u(U::MouthUpperUpRight));
+
+    set(A::BrowDownLeft, u(U::BrowLowererLeft));
+    set(A::BrowDownRight, u(U::BrowLowererRight));
+    set(A::BrowInnerUp, avg(U::BrowInnerUpLeft, U::BrowInnerUpRight));
+    set(A::BrowOuterUpLeft, u(U::BrowOuterUpLeft));
+    set(A::BrowOuterUpRight, u(U::BrowOuterUpRight));
+
+    set(A::CheekPuff, avg(U::CheekPuffLeft, U::CheekPuffRight));
+    set(A::CheekSquintLeft, u(U::CheekSquintLeft));
+    set(A::CheekSquintRight, u(U::CheekSquintRight));
+    set(A::NoseSneerLeft, u(U::NoseSneerLeft));
+    set(A::NoseSneerRight, u(U::NoseSneerRight));
+    set(A::TongueOut, u(U::TongueOut));
+
+    set(A::LeftEyeYaw, left_yaw / LIVE_LINK_ANGLE_RAD);
+    set(A::LeftEyePitch, left_pitch / LIVE_LINK_ANGLE_RAD);
+    set(A::RightEyeYaw, right_yaw / LIVE_LINK_ANGLE_RAD);
+    set(A::RightEyePitch, right_pitch / LIVE_LINK_ANGLE_RAD);
+
+    out
+}
+
+/// Maps ARKit blendshapes, in [`ArkitShape`] order, onto unified expressions.
+pub(crate) fn arkit_to_unified(arkit: &[f32]) -> Option<UnifiedShapes> {
+    use ArkitShape as A;
+    use UnifiedExpressions as U;
+
+    let mut shapes: UnifiedShapes = [0.0; NUM_SHAPES];
+    if arkit.len() < NUM_ARKIT_BLENDSHAPES {
+        log::warn!(
+            "ARKit data is too short: {} < {}",
+            arkit.len(),
+            NUM_ARKIT_BLENDSHAPES
+        );
+        return None;
+    }
+
+    let geta = |shape: A| arkit[shape as usize];
+
+    shapes.setu(
+        U::EyeLeftX,
+        geta(A::EyeLookInLeft) - geta(A::EyeLookOutLeft),
+    );
+    shapes.setu(
+        U::EyeRightX,
+        geta(A::EyeLookOutRight) - geta(A::EyeLookInRight),
+    );
+    shapes.setu(
+        U::EyeY,
+        (geta(A::EyeLookUpLeft) + geta(A::EyeLookUpRight)
+            - geta(A::EyeLookDownLeft)
+            - geta(A::EyeLookDownRight))
+            * 0.5,
+    );
+
+    for (unified, shape) in [
+        (U::EyeClosedLeft, A::EyeBlinkLeft),
+        (U::EyeClosedRight, A::EyeBlinkRight),
+        (U::EyeSquintLeft, A::EyeSquintLeft),
+        (U::EyeSquintRight, A::EyeSquintRight),
+        (U::EyeWideLeft, A::EyeWideLeft),
+        (U::EyeWideRight, A::EyeWideRight),
+        (U::BrowLowererLeft, A::BrowDownLeft),
+        (U::BrowLowererRight, A::BrowDownRight),
+        (U::BrowPinchLeft, A::BrowDownLeft),
+        (U::BrowPinchRight, A::BrowDownRight),
+        (U::BrowInnerUpLeft, A::BrowInnerUp),
+        (U::BrowInnerUpRight, A::BrowInnerUp),
+        (U::BrowOuterUpLeft, A::BrowOuterUpLeft),
+        (U::BrowOuterUpRight, A::BrowOuterUpRight),
+        (U::CheekPuffLeft, A::CheekPuff),
+        (U::CheekPuffRight, A::CheekPuff),
+        (U::CheekSquintLeft, A::CheekSquintLeft),
+        (U::CheekSquintRight, A::CheekSquintRight),
+        (U::NoseSneerLeft, A::NoseSneerLeft),
+        (U::NoseSneerRight, A::NoseSneerRight),
+        (U::JawOpen, A::JawOpen),
+        (U::JawForward, A::JawForward),
+        (U::JawLeft, A::JawLeft),
+        (U::JawRight, A::JawRight),
+        (U::MouthClosed, A::MouthClose),
+        (U::LipFunnelUpperLeft, A::MouthFunnel),
+        (U::LipFunnelUpperRight, A::MouthFunnel),
+        (U::LipFunnelLowerLeft, A::MouthFunnel),
+        (U::LipFunnelLowerRight, A::MouthFunnel),
+        (U::LipPuckerUpperLeft, A::MouthPucker),
+        (U::LipPuckerUpperRight, A::MouthPucker),
+        (U::LipPuckerLowerLeft, A::MouthPucker),
+        (U::LipPuckerLowerRight, A::MouthPucker),
+        (U::LipSuckUpperLeft, A::MouthRollUpper),
+        (U::LipSuckUpperRight, A::MouthRollUpper),
+        (U::LipSuckLowerLeft, A::MouthRollLower),
+        (U::LipSuckLowerRight, A::MouthRollLower),
+        (U::MouthUpperLeft, A::MouthLeft),
+        (U::MouthLowerLeft, A::MouthLeft),
+        (U::MouthUpperRight, A::MouthRight),
+        (U::MouthLowerRight, A::MouthRight),
+        (U::MouthCornerPullLeft, A::MouthSmileLeft),
+        (U::MouthCornerPullRight, A::MouthSmileRight),
+        (U::MouthCornerSlantLeft, A::MouthSmileLeft),
+        (U::MouthCornerSlantRight, A::MouthSmileRight),
+        (U::MouthFrownLeft, A::MouthFrownLeft),
+        (U::MouthFrownRight, A::MouthFrownRight),
+        (U::MouthDimpleLeft, A::MouthDimpleLeft),
+        (U::MouthDimpleRight, A::MouthDimpleRight),
+        (U::MouthStretchLeft, A::MouthStretchLeft),
+        (U::MouthStretchRight, A::MouthStretchRight),
+        (U::MouthRaiserUpper, A::MouthShrugUpper),
+        (U::MouthRaiserLower, A::MouthShrugLower),
+        (U::MouthPressLeft, A::MouthPressLeft),
+        (U::MouthPressRight, A::MouthPressRight),
+        (U::MouthLowerDownLeft, A::MouthLowerDownLeft),
+        (U::MouthLowerDownRight, A::MouthLowerDownRight),
+        (U::MouthUpperUpLeft, A::MouthUpperUpLeft),
+        (U::MouthUpperUpRight, A::MouthUpperUpRight),
+        (U::TongueOut, A::TongueOut),
+    ] {
+        shapes.setu(unified, geta(shape));
+    }
+
+    Some(shapes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn converts_shapes_and_gaze() {
+        let mut data = UnifiedTrackingData::default();
+        data.setu(UnifiedExpressions::JawOpen, 0.8);
+        data.setu(UnifiedExpressions::CheekPuffLeft, 1.);
+        // both eyes turned fully to the left
+        data.eyes[0] = Some(Vec3::new(0., EYE_RANGE_RAD, 0.));
+
+        let arkit = unified_to_arkit(&data);
+        assert_eq!(arkit[ArkitShape::JawOpen as usize], 0.8);
+        assert_eq!(arkit[ArkitShape::CheekPuff as usize], 0.5);
+        assert_eq!(arkit[ArkitShape::EyeLookOutLeft as usize], 1.);
+        assert_eq!(arkit[ArkitShape::EyeLookInRight as usize], 1.);
+        assert_eq!(arkit[ArkitShape::EyeLookInLeft as usize], 0.);
+        assert_eq!(NUM_ARKIT_BLENDSHAPES, 52);
+        assert_eq!(ArkitShape::COUNT, 61);
+    }
+
+    #[test]
+    fn arkit_roundtrips_through_unified() {
+        let mut data = UnifiedTrackingData::default();
+        data.setu(UnifiedExpressions::MouthCornerPullLeft, 0.4);
+        data.setu(UnifiedExpressions::EyeClosedRight, 1.);
+        data.setu(UnifiedExpressions::TongueOut, 0.3);
+
+        let shapes = arkit_to_unified(&unified_to_arkit(&data)).unwrap();
+        assert_eq!(shapes.getu(UnifiedExpressions::MouthCornerPullLeft), 0.4);
+        assert_eq!(shapes.getu(UnifiedExpressions::EyeClosedRight), 1.);
+        assert_eq!(shapes.getu(UnifiedExpressions::TongueOut), 0.3);
+
+        assert!(arkit_to_unified(&[0.; 10]).is_none());
+    }
+}
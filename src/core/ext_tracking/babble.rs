@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    net::{Ipv4Addr, SocketAddr},
     sync::{
         mpsc::{sync_channel, Receiver, SyncSender},
         Arc,
@@ -12,13 +12,19 @@ use std::{
 use colored::{Color, Colorize};
 use once_cell::sync::Lazy;
 use rosc::{OscPacket, OscType};
+use strum::EnumCount;
 
 use crate::core::{
-    ext_tracking::unified::UnifiedExpressions, AppState, INSTRUCTIONS_END, INSTRUCTIONS_START,
-    TRACK_ON,
+    config::BabbleConfig, ext_tracking::unified::UnifiedExpressions, AppState, INSTRUCTIONS_END,
+    INSTRUCTIONS_START, TRACK_ON,
 };
 
-use super::{unified::UnifiedTrackingData, FaceReceiver};
+use super::{
+    bind_udp,
+    region::{Region, RegionTimes},
+    unified::UnifiedTrackingData,
+    FaceReceiver, LoopGuard, LoopHandle,
+};
 
 static STA_BABL1: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "BABBLE".color(Color::Green)).into());
 static STA_BABL0: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "BABBLE".color(Color::Red)).into());
@@ -27,21 +33,25 @@ static STA_ETVR0: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "ETVR".color(Color
 
 pub(super) struct BabbleEtvrReceiver {
     listen_port: u16,
+    ports: BabbleConfig,
     sender: SyncSender<Box<BabbleEtvrEvent>>,
     receiver: Receiver<Box<BabbleEtvrEvent>>,
-    last_received_babble: Instant,
-    last_received_etvr: Instant,
+    last_received_babble: Option<Instant>,
+    last_received_etvr: Option<Instant>,
+    guard: LoopGuard,
 }
 
 impl BabbleEtvrReceiver {
-    pub fn new(listen_port: u16) -> Self {
+    pub fn new(listen_port: u16, ports: &BabbleConfig) -> Self {
         let (sender, receiver) = sync_channel(128);
         Self {
             listen_port,
+            ports: ports.clone(),
             sender,
             receiver,
-            last_received_babble: Instant::now(),
-            last_received_etvr: Instant::now(),
+            last_received_babble: None,
+            last_received_etvr: None,
+            guard: LoopGuard::default(),
         }
     }
 }
@@ -51,10 +61,15 @@ impl FaceReceiver for BabbleEtvrReceiver {
         let sender = self.sender.clone();
         let listen_port = self.listen_port;
 
-        let babble_recv_port = listen_port + 10;
-        let babble_http_port = babble_recv_port + 1;
-        let etvr_recv_port = babble_recv_port + 10;
-        let etvr_http_port = etvr_recv_port + 1;
+        let Some([babble_recv_port, babble_http_port, etvr_recv_port, etvr_http_port]) =
+            self.ports.ports(listen_port)
+        else {
+            log::error!(
+                "Babble/ETVR: port offsets are too large for listen port {}",
+                listen_port
+            );
+            return;
+        };
 
         log::info!("{}", *INSTRUCTIONS_START);
         log::info!("");
@@ -113,7 +128,8 @@ impl FaceReceiver for BabbleEtvrReceiver {
         log::info!("");
         log::info!("{}", *INSTRUCTIONS_END);
 
-        thread::spawn(move || babble_loop(listen_port, sender));
+        let handle = self.guard.handle();
+        thread::spawn(move || babble_loop(listen_port, sender, handle));
     }
 
     fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
@@ -121,44 +137,46 @@ impl FaceReceiver for BabbleEtvrReceiver {
             data.shapes[event.expression as usize] = event.value;
 
             if (event.expression as usize) < (UnifiedExpressions::BrowPinchRight as usize) {
-                self.last_received_etvr = Instant::now();
+                self.last_received_etvr = Some(Instant::now());
             } else {
-                self.last_received_babble = Instant::now();
+                self.last_received_babble = Some(Instant::now());
             }
         }
 
-        if self.last_received_babble.elapsed() < Duration::from_secs(1) {
+        if self
+            .last_received_babble
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
             state.status.add_item(STA_BABL1.clone());
         } else {
             state.status.add_item(STA_BABL0.clone());
         }
 
-        if self.last_received_etvr.elapsed() < Duration::from_secs(1) {
+        if self
+            .last_received_etvr
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
             state.status.add_item(STA_ETVR1.clone());
         } else {
             state.status.add_item(STA_ETVR0.clone());
         }
     }
-}
 
-fn babble_loop(listen_port: u16, mut sender: SyncSender<Box<BabbleEtvrEvent>>) {
-    loop {
-        if let Some(()) = receive_babble_osc(listen_port, &mut sender) {
-            break;
-        } else {
-            thread::sleep(Duration::from_millis(5000));
-        }
+    /// ETVR sends the eyes, Babble everything else.
+    fn last_update(&self) -> RegionTimes {
+        let mut times = [self.last_received_babble; Region::COUNT];
+        times[Region::Eyes as usize] = self.last_received_etvr;
+        times
     }
 }
 
-fn receive_babble_osc(
-    listen_port: u16,
-    sender: &mut SyncSender<Box<BabbleEtvrEvent>>,
-) -> Option<()> {
-    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
-    let listener = UdpSocket::bind(SocketAddr::new(ip, listen_port)).expect("bind listener socket");
+fn babble_loop(listen_port: u16, sender: SyncSender<Box<BabbleEtvrEvent>>, handle: LoopHandle) {
+    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), listen_port);
+    let Some(listener) = bind_udp("Babble/ETVR", addr) else {
+        return;
+    };
     let mut buf = [0u8; rosc::decoder::MTU];
-    loop {
+    while !handle.is_stopped() {
         if let Ok((size, _addr)) = listener.recv_from(&mut buf) {
             if let Ok((_, OscPacket::Message(packet))) = rosc::decoder::decode_udp(&buf[..size]) {
                 if packet.args.is_empty() {
@@ -0,0 +1,315 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use colored::{Color, Colorize};
+use once_cell::sync::Lazy;
+use rosc::OscType;
+use serde::{Deserialize, Serialize};
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::core::{AppState, AvatarParameters};
+
+use super::unified::{UnifiedExpressions, UnifiedTrackingData};
+
+static STA_CALIB: Lazy<Arc<str>> =
+    Lazy::new(|| format!("{}", "CALIB".color(Color::BrightYellow)).into());
+
+const FILE_PREFIX: &str = "oscavmgr-calibration";
+
+/// Avatar bool that starts a neutral face capture when it turns true
+const PARAM_NEUTRAL: &str = "CalibrateNeutral";
+/// Avatar bool that learns each shape's range for as long as it is true
+const PARAM_LEARN: &str = "CalibrateLearn";
+
+const NEUTRAL_CAPTURE_TIME: Duration = Duration::from_secs(2);
+
+/// A learned range is never narrower than this, so noise doesn't become full motion.
+const MIN_SPAN: f32 = 0.1;
+
+/// Gaze shapes are signed and left alone.
+const CALIBRATED: Range<usize> =
+    UnifiedExpressions::EyeClosedRight as usize..UnifiedExpressions::COUNT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShapeCalibration {
+    pub min: f32,
+    pub max: f32,
+    /// Resting value, which is mapped to 0 when above `min`
+    pub neutral: f32,
+}
+
+impl Default for ShapeCalibration {
+    fn default() -> Self {
+        Self {
+            min: 0.,
+            max: 1.,
+            neutral: 0.,
+        }
+    }
+}
+
+impl ShapeCalibration {
+    fn apply(&self, value: f32) -> f32 {
+        let low = self.min.max(self.neutral);
+        let span = (self.max - low).max(MIN_SPAN);
+        ((value - low) / span).clamp(0., 1.)
+    }
+}
+
+struct NeutralCapture {
+    start: Instant,
+    sum: Vec<f32>,
+    frames: u32,
+}
+
+/// Remaps raw tracker values so that each shape spans 0..1 for this user.
+///
+/// Shapes without a calibration pass through untouched.
+pub struct Calibration {
+    path: String,
+    shapes: Vec<Option<ShapeCalibration>>,
+    learned: Option<Vec<(f32, f32)>>,
+    capture: Option<NeutralCapture>,
+    neutral_param: bool,
+}
+
+impl Calibration {
+    pub fn path(config_dir: &str, profile: &str) -> String {
+        format!("{}/{}-{}.json", config_dir, FILE_PREFIX, profile)
+    }
+
+    pub fn load(config_dir: &str, profile: &str) -> Self {
+        let path = Self::path(config_dir, profile);
+        let mut shapes = vec![None; UnifiedExpressions::COUNT];
+
+        let saved: Option<BTreeMap<String, ShapeCalibration>> =
+            File::open(&path)
+                .ok()
+                .and_then(|file| match serde_json::from_reader(file) {
+                    Ok(saved) => Some(saved),
+                    Err(e) => {
+                        log::warn!("Ignoring calibration {}: {}", path, e);
+                        None
+                    }
+                });
+
+        if let Some(saved) = saved {
+            log::info!(
+                "Loaded calibration for {} shapes from {}",
+                saved.len(),
+                path
+            );
+            for (name, calibration) in saved {
+                match UnifiedExpressions::from_str(&name) {
+                    Ok(e) if CALIBRATED.contains(&(e as usize)) => {
+                        shapes[e as usize] = Some(calibration)
+                    }
+                    _ => log::warn!("Calibration: unknown shape {}", name),
+                }
+            }
+        }
+
+        Self {
+            path,
+            shapes,
+            learned: None,
+            capture: None,
+            neutral_param: false,
+        }
+    }
+
+    fn save(&self) {
+        let saved: BTreeMap<&str, ShapeCalibration> = UnifiedExpressions::iter()
+            .zip(self.shapes.iter())
+            .filter_map(|(e, c)| Some((e.into(), (*c)?)))
+            .collect();
+
+        log::info!("Saving calibration to {}", &self.path);
+        let result = serde_json::to_string_pretty(&saved)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&self.path, json));
+        if let Err(e) = result {
+            log::warn!("Could not save calibration to {}: {}", self.path, e);
+        }
+    }
+
+    /// Runs on the raw values in `data`, then replaces them with calibrated ones.
+    pub fn step(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        if self.update(data, &state.params, Instant::now()) {
+            state.status.add_item(STA_CALIB.clone());
+        }
+        self.apply(data);
+    }
+
+    /// Handles the calibration parameters. Returns true while calibrating.
+    fn update(
+        &mut self,
+        data: &UnifiedTrackingData,
+        params: &AvatarParameters,
+        now: Instant,
+    ) -> bool {
+        let neutral = matches!(params.get(PARAM_NEUTRAL), Some(OscType::Bool(true)));
+        if neutral && !self.neutral_param {
+            log::info!("Calibration: capturing neutral face, hold still...");
+            self.capture = Some(NeutralCapture {
+                start: now,
+                sum: vec![0.; UnifiedExpressions::COUNT],
+                frames: 0,
+            });
+        }
+        self.neutral_param = neutral;
+
+        if let Some(capture) = self.capture.as_mut() {
+            for i in CALIBRATED {
+                capture.sum[i] += data.shapes[i];
+            }
+            capture.frames += 1;
+
+            if now.saturating_duration_since(capture.start) >= NEUTRAL_CAPTURE_TIME {
+                let capture = self.capture.take().unwrap();
+                for i in CALIBRATED {
+                    let shape = self.shapes[i].get_or_insert_with(Default::default);
+                    shape.neutral = capture.sum[i] / capture.frames as f32;
+                }
+                log::info!("Calibration: neutral face captured");
+                self.save();
+            }
+        }
+
+        let learn = matches!(params.get(PARAM_LEARN), Some(OscType::Bool(true)));
+        match (learn, self.learned.as_mut()) {
+            (true, None) => {
+                log::info!("Calibration: learning ranges, make every face you can...");
+                self.learned = Some(vec![(f32::INFINITY, f32::NEG_INFINITY); data.shapes.len()]);
+            }
+            (true, Some(learned)) => {
+                for i in CALIBRATED {
+                    let (min, max) = &mut learned[i];
+                    *min = min.min(data.shapes[i]);
+                    *max = max.max(data.shapes[i]);
+                }
+            }
+            (false, Some(_)) => {
+                let learned = self.learned.take().unwrap();
+                let mut count = 0;
+                for i in CALIBRATED {
+                    let (min, max) = learned[i];
+                    // shapes the tracker never moved keep what they had
+                    if max - min < MIN_SPAN {
+                        continue;
+                    }
+                    let shape = self.shapes[i].get_or_insert_with(Default::default);
+                    shape.min = min;
+                    shape.max = max;
+                    count += 1;
+                }
+                log::info!("Calibration: learned the range of {} shapes", count);
+                self.save();
+            }
+            (false, None) => {}
+        }
+
+        self.capture.is_some() || self.learned.is_some()
+    }
+
+    fn apply(&self, data: &mut UnifiedTrackingData) {
+        for i in CALIBRATED {
+            if let Some(shape) = self.shapes[i].as_ref() {
+                data.shapes[i] = shape.apply(data.shapes[i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::harness::config_dir;
+
+    fn calibration() -> Calibration {
+        Calibration {
+            path: String::new(),
+            shapes: vec![None; UnifiedExpressions::COUNT],
+            learned: None,
+            capture: None,
+            neutral_param: false,
+        }
+    }
+
+    fn params(name: &str, value: bool) -> AvatarParameters {
+        [(name.into(), OscType::Bool(value))].into_iter().collect()
+    }
+
+    #[test]
+    fn remaps_ranges() {
+        let shape = ShapeCalibration {
+            min: 0.1,
+            max: 0.6,
+            neutral: 0.2,
+        };
+        assert_eq!(shape.apply(0.1), 0.);
+        assert_eq!(shape.apply(0.2), 0.);
+        assert_eq!(shape.apply(0.4), 0.5);
+        assert_eq!(shape.apply(0.9), 1.);
+    }
+
+    #[test]
+    fn learns_ranges_while_enabled() {
+        let dir = config_dir();
+        let mut calib = Calibration {
+            path: Calibration::path(&dir, "test-learn"),
+            ..calibration()
+        };
+        let mut data = UnifiedTrackingData::default();
+        let now = Instant::now();
+
+        calib.update(&data, &params(PARAM_LEARN, true), now);
+        for jaw in [0.05, 0.3, 0.7, 0.2] {
+            data.setu(UnifiedExpressions::JawOpen, jaw);
+            assert!(calib.update(&data, &params(PARAM_LEARN, true), now));
+        }
+        assert!(!calib.update(&data, &params(PARAM_LEARN, false), now));
+
+        // the jaw never got past 0.7, so that now counts as fully open
+        data.setu(UnifiedExpressions::JawOpen, 0.7);
+        calib.apply(&mut data);
+        assert_eq!(data.getu(UnifiedExpressions::JawOpen), 1.);
+        // shapes that never moved are not touched
+        data.setu(UnifiedExpressions::TongueOut, 0.4);
+        calib.apply(&mut data);
+        assert_eq!(data.getu(UnifiedExpressions::TongueOut), 0.4);
+
+        let loaded = Calibration::load(&dir, "test-learn");
+        assert_eq!(loaded.shapes, calib.shapes);
+    }
+
+    #[test]
+    fn captures_neutral_face() {
+        let dir = config_dir();
+        let mut calib = Calibration {
+            path: Calibration::path(&dir, "test-neutral"),
+            ..calibration()
+        };
+        let mut data = UnifiedTrackingData::default();
+        data.setu(UnifiedExpressions::EyeSquintLeft, 0.2);
+        let start = Instant::now();
+
+        assert!(calib.update(&data, &params(PARAM_NEUTRAL, true), start));
+        assert!(!calib.update(
+            &data,
+            &params(PARAM_NEUTRAL, true),
+            start + NEUTRAL_CAPTURE_TIME
+        ));
+
+        calib.apply(&mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeSquintLeft), 0.);
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    array,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::core::{config::Config, AppState};
+
+use super::{
+    create_receiver,
+    region::{Liveness, Region, RegionTimes},
+    unified::UnifiedTrackingData,
+    FaceReceiver,
+};
+
+struct Source {
+    name: Arc<str>,
+    receiver: Box<dyn FaceReceiver>,
+    data: UnifiedTrackingData,
+    liveness: Liveness,
+}
+
+impl Source {
+    fn new(name: &str, receiver: Box<dyn FaceReceiver>) -> Self {
+        Self {
+            name: name.into(),
+            receiver,
+            data: UnifiedTrackingData::default(),
+            liveness: Liveness::default(),
+        }
+    }
+}
+
+/// Runs several receivers at once and takes each face region from the
+/// first source in that region's priority list that is still live.
+pub(super) struct CompositeReceiver {
+    sources: Vec<Source>,
+    priorities: [Vec<usize>; Region::COUNT],
+    active: [Option<usize>; Region::COUNT],
+    stale_after: Duration,
+}
+
+impl CompositeReceiver {
+    pub fn new(config: &Config) -> Self {
+        let compositor = &config.compositor;
+        let sources = compositor
+            .sources
+            .iter()
+            .map(|source| Source::new(&source.name, create_receiver(&source.face, config)))
+            .collect();
+
+        let regions = &compositor.regions;
+        let priorities = Region::iter()
+            .map(|region| {
+                let names = match region {
+                    Region::Eyes => &regions.eyes,
+                    Region::Brows => &regions.brows,
+                    Region::Mouth => &regions.mouth,
+                    Region::Tongue => &regions.tongue,
+                };
+                if names.is_empty() {
+                    return (0..compositor.sources.len()).collect();
+                }
+                // names were checked against the sources when the config was loaded
+                names
+                    .iter()
+                    .filter_map(|name| compositor.sources.iter().position(|s| &s.name == name))
+                    .collect()
+            })
+            .collect::<Vec<Vec<usize>>>()
+            .try_into()
+            .unwrap();
+
+        Self {
+            sources,
+            priorities,
+            active: [None; Region::COUNT],
+            stale_after: Duration::from_millis(compositor.stale_after_ms),
+        }
+    }
+
+    fn compose(&mut self, now: Instant, data: &mut UnifiedTrackingData) {
+        for source in self.sources.iter_mut() {
+            let last_update = source.receiver.last_update();
+            source.liveness.update(&source.data, last_update, now);
+        }
+
+        for region in Region::iter() {
+            let priority = &self.priorities[region as usize];
+            let Some(&idx) = priority
+                .iter()
+                .find(|&&idx| {
+                    self.sources[idx]
+                        .liveness
+                        .is_live(region, now, self.stale_after)
+                })
+                .or_else(|| priority.first())
+            else {
+                continue;
+            };
+
+            if self.active[region as usize] != Some(idx) {
+                let name: &str = region.into();
+                log::info!("Compositor: {} from {}", name, self.sources[idx].name);
+                self.active[region as usize] = Some(idx);
+            }
+
+            let source = &self.sources[idx].data;
+            let range = region.range();
+            data.shapes[range.clone()].copy_from_slice(&source.shapes[range]);
+            if let Region::Eyes = region {
+                data.eyes = source.eyes;
+            }
+        }
+    }
+}
+
+impl FaceReceiver for CompositeReceiver {
+    fn start_loop(&mut self) {
+        for source in self.sources.iter_mut() {
+            log::info!("Compositor: starting source {}", source.name);
+            source.receiver.start_loop();
+        }
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        for source in self.sources.iter_mut() {
+            source.receiver.receive(&mut source.data, state);
+        }
+        self.compose(Instant::now(), data);
+    }
+
+    /// Each region's time comes from the source it is currently taken from.
+    fn last_update(&self) -> RegionTimes {
+        array::from_fn(|region| {
+            self.active[region].and_then(|idx| self.sources[idx].receiver.last_update()[region])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::CompositorSource;
+    use crate::core::ext_tracking::unified::UnifiedExpressions;
+    use crate::FaceSetup;
+    use glam::Vec3;
+
+    /// Got its last packet at a fixed time
+    struct Silent(Instant);
+
+    impl FaceReceiver for Silent {
+        fn start_loop(&mut self) {}
+        fn receive(&mut self, _data: &mut UnifiedTrackingData, _: &mut AppState) {}
+        fn last_update(&self) -> RegionTimes {
+            [Some(self.0); Region::COUNT]
+        }
+    }
+
+    fn compositor(eyes: &[&str]) -> CompositeReceiver {
+        let mut config = Config::default();
+        for name in ["face", "eyes"] {
+            config.compositor.sources.push(CompositorSource {
+                name: name.into(),
+                face: FaceSetup::Dummy,
+            });
+        }
+        config.compositor.regions.eyes = eyes.iter().map(|s| s.to_string()).collect();
+        CompositeReceiver::new(&config)
+    }
+
+    #[test]
+    fn takes_each_region_from_its_first_live_source() {
+        let mut comp = compositor(&["eyes", "face"]);
+        let mut data = UnifiedTrackingData::default();
+        let start = Instant::now();
+
+        comp.sources[0].data.setu(UnifiedExpressions::JawOpen, 0.5);
+        comp.sources[0]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.2);
+        comp.sources[1]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.9);
+        comp.sources[1].data.eyes[0] = Some(Vec3::X);
+        comp.compose(start, &mut data);
+
+        assert_eq!(data.getu(UnifiedExpressions::JawOpen), 0.5);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.9);
+        assert_eq!(data.eyes[0], Some(Vec3::X));
+    }
+
+    #[test]
+    fn falls_back_when_a_source_goes_stale() {
+        let mut comp = compositor(&["eyes", "face"]);
+        let mut data = UnifiedTrackingData::default();
+        let start = Instant::now();
+
+        comp.sources[1]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.9);
+        comp.compose(start, &mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.9);
+
+        // the eye tracker freezes while the face tracker keeps moving
+        let later = start + Duration::from_secs(1);
+        comp.sources[0]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.3);
+        comp.compose(later, &mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.3);
+
+        // and takes over again once it resumes
+        comp.sources[1]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.8);
+        comp.compose(later, &mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.8);
+    }
+
+    #[test]
+    fn falls_back_when_a_source_goes_silent() {
+        let mut comp = compositor(&["eyes", "face"]);
+        let mut data = UnifiedTrackingData::default();
+        let start = Instant::now();
+        comp.sources[1].receiver = Box::new(Silent(start));
+
+        comp.sources[0]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.3);
+        comp.sources[1]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.9);
+        comp.compose(start, &mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.9);
+
+        // nothing has arrived from the eye tracker since, whatever its values do
+        let later = start + Duration::from_secs(1);
+        comp.sources[0]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.4);
+        comp.sources[1]
+            .data
+            .setu(UnifiedExpressions::EyeClosedLeft, 0.8);
+        comp.compose(later, &mut data);
+        assert_eq!(data.getu(UnifiedExpressions::EyeClosedLeft), 0.4);
+    }
+}
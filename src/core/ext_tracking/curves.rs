@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, fmt::Write, str::FromStr};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use strum::EnumCount;
+
+use super::unified::{UnifiedExpressions, UnifiedTrackingData};
+
+/// Inputs shown by the `curves` subcommand
+const TABLE_STEPS: usize = 10;
+
+/// Artistic shaping of a single shape, applied after calibration and filtering.
+///
+/// In order: the deadzone is cut off and the rest stretched back to 0..1, then the
+/// points remap it, then gamma, gain and offset are applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShapeCurve {
+    /// Values below this count as 0
+    pub deadzone: f32,
+    /// Piecewise-linear `[input, output]` pairs, with inputs in increasing order.
+    /// Inputs outside the first and last point keep the first and last output.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub points: Vec<[f32; 2]>,
+    /// Above 1 holds back small values, below 1 boosts them
+    pub gamma: f32,
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for ShapeCurve {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.,
+            points: Vec::new(),
+            gamma: 1.,
+            gain: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl ShapeCurve {
+    pub fn apply(&self, value: f32) -> f32 {
+        let mut value = ((value - self.deadzone) / (1. - self.deadzone)).clamp(0., 1.);
+
+        if let Some(i) = self.points.iter().position(|[x, _]| *x >= value) {
+            value = match i {
+                0 => self.points[0][1],
+                i => {
+                    let [x0, y0] = self.points[i - 1];
+                    let [x1, y1] = self.points[i];
+                    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+                }
+            };
+        } else if let Some([_, y]) = self.points.last() {
+            value = *y;
+        }
+
+        (value.powf(self.gamma) * self.gain + self.offset).clamp(0., 1.)
+    }
+
+    pub fn validate(&self, name: &str) -> anyhow::Result<()> {
+        if !(0.0..1.0).contains(&self.deadzone) {
+            bail!(
+                "curves.{}.deadzone must be in 0..1, got {}",
+                name,
+                self.deadzone
+            );
+        }
+        if self.gamma.is_nan() || self.gamma <= 0. {
+            bail!(
+                "curves.{}.gamma must be greater than 0, got {}",
+                name,
+                self.gamma
+            );
+        }
+        if !self.gain.is_finite() || !self.offset.is_finite() {
+            bail!("curves.{}.gain and offset must be numbers", name);
+        }
+        for (i, [x, y]) in self.points.iter().enumerate() {
+            if !(0.0..=1.0).contains(x) || !(0.0..=1.0).contains(y) {
+                bail!("curves.{}.points[{}] must be within 0..1", name, i);
+            }
+            if i > 0 && *x <= self.points[i - 1][0] {
+                bail!(
+                    "curves.{}.points[{}]: inputs must be in increasing order",
+                    name,
+                    i
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Looks up a shape by the name used in `[curves]`. Signed gaze shapes can't have a curve.
+pub fn curve_shape(name: &str) -> anyhow::Result<UnifiedExpressions> {
+    let shape = UnifiedExpressions::from_str(name)
+        .ok()
+        .with_context(|| format!("curves.{}: unknown shape", name))?;
+    if (shape as usize) < UnifiedExpressions::EyeClosedRight as usize {
+        bail!("curves.{}: eye look shapes can't have a curve", name);
+    }
+    Ok(shape)
+}
+
+pub struct Curves {
+    shapes: Vec<Option<ShapeCurve>>,
+}
+
+impl Curves {
+    /// The config is validated by then, so unknown names are not expected here.
+    pub fn new(config: &BTreeMap<String, ShapeCurve>) -> Self {
+        let mut shapes = vec![None; UnifiedExpressions::COUNT];
+        for (name, curve) in config.iter() {
+            if let Ok(shape) = curve_shape(name) {
+                shapes[shape as usize] = Some(curve.clone());
+            }
+        }
+        Self { shapes }
+    }
+
+    pub fn apply(&self, data: &mut UnifiedTrackingData) {
+        for (value, curve) in data.shapes.iter_mut().zip(self.shapes.iter()) {
+            if let Some(curve) = curve.as_ref() {
+                *value = curve.apply(*value);
+            }
+        }
+    }
+}
+
+/// Every configured curve, sampled from 0 to 1.
+pub fn curve_table(config: &BTreeMap<String, ShapeCurve>) -> String {
+    let width = config.keys().map(String::len).max().unwrap_or(0).max(5);
+
+    let mut table = format!("{:width$}", "Input");
+    for i in 0..=TABLE_STEPS {
+        let _ = write!(table, " {:>4.1}", i as f32 / TABLE_STEPS as f32);
+    }
+    table.push('\n');
+
+    for (name, curve) in config.iter() {
+        let _ = write!(table, "{:width$}", name);
+        for i in 0..=TABLE_STEPS {
+            let _ = write!(
+                table,
+                " {:>4.2}",
+                curve.apply(i as f32 / TABLE_STEPS as f32)
+            );
+        }
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_values_in_order() {
+        let deadzone = ShapeCurve {
+            deadzone: 0.2,
+            ..Default::default()
+        };
+        assert_eq!(deadzone.apply(0.1), 0.);
+        assert!((deadzone.apply(0.6) - 0.5).abs() < 1e-6);
+        assert_eq!(deadzone.apply(1.), 1.);
+
+        let stronger = ShapeCurve {
+            gamma: 0.5,
+            gain: 1.2,
+            ..Default::default()
+        };
+        assert!((stronger.apply(0.25) - 0.6).abs() < 1e-6);
+        assert_eq!(stronger.apply(0.9), 1.);
+
+        let s_curve = ShapeCurve {
+            points: vec![[0.2, 0.], [0.5, 0.5], [0.8, 1.]],
+            ..Default::default()
+        };
+        assert_eq!(s_curve.apply(0.1), 0.);
+        assert!((s_curve.apply(0.35) - 0.25).abs() < 1e-6);
+        assert_eq!(s_curve.apply(0.9), 1.);
+    }
+
+    #[test]
+    fn rejects_bad_curves() {
+        assert!(curve_shape("CheekPuffLeft").is_ok());
+        assert!(curve_shape("EyeLeftX").is_err());
+        assert!(curve_shape("CheekPuf").is_err());
+
+        let unordered = ShapeCurve {
+            points: vec![[0.5, 0.], [0.5, 1.]],
+            ..Default::default()
+        };
+        let e = unordered.validate("JawOpen").unwrap_err();
+        assert!(format!("{e}").contains("curves.JawOpen.points[1]"));
+    }
+
+    #[test]
+    fn prints_table() {
+        let config = BTreeMap::from([(
+            "JawOpen".to_string(),
+            ShapeCurve {
+                gain: 0.5,
+                ..Default::default()
+            },
+        )]);
+        let table = curve_table(&config);
+        let mut lines = table.lines();
+        assert!(lines.next().unwrap().starts_with("Input    0.0  0.1"));
+        assert!(lines.next().unwrap().ends_with(" 0.45 0.50"));
+    }
+}
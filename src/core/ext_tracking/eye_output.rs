@@ -0,0 +1,134 @@
+use glam::Vec3;
+use rosc::OscType;
+
+use crate::core::config::EyeOutputMode;
+
+/// Distance between the eyes, in meters
+const IPD_M: f32 = 0.063;
+/// Gaze lines that cross further out than this count as parallel
+const MAX_FOCUS_DISTANCE_M: f32 = 10.;
+
+/// Where an eye looks, in VRChat's axes: +X right, +Y up, +Z forward.
+fn direction(eye: Vec3) -> Vec3 {
+    let (pitch, yaw) = (eye.x, eye.y);
+    Vec3::new(
+        -yaw.sin() * pitch.cos(),
+        pitch.sin(),
+        yaw.cos() * pitch.cos(),
+    )
+}
+
+/// How far ahead the two gaze lines cross, from how much the eyes turn towards each other.
+fn vergence_distance(left: Vec3, right: Vec3) -> f32 {
+    // a positive yaw looks left, so this grows as the eyes converge
+    let convergence = right.y.tan() - left.y.tan();
+    if convergence > IPD_M / MAX_FOCUS_DISTANCE_M {
+        IPD_M / convergence
+    } else {
+        MAX_FOCUS_DISTANCE_M
+    }
+}
+
+/// The address and arguments to send the gaze with, while the left eye is tracked.
+pub(super) fn eye_message(
+    mode: EyeOutputMode,
+    eyes: [Option<Vec3>; 2],
+    focus_distance_m: f32,
+) -> Option<(&'static str, Vec<OscType>)> {
+    let left = eyes[0]?;
+    let right = eyes[1].unwrap_or(left);
+    let center = (left + right) * 0.5;
+    let distance = || match eyes {
+        [Some(left), Some(right)] => vergence_distance(left, right),
+        _ => focus_distance_m,
+    };
+
+    // VRChat pitches down and yaws right
+    let degrees = |eye: Vec3| [-eye.x.to_degrees(), -eye.y.to_degrees()];
+    let args =
+        |values: &[f32]| -> Vec<OscType> { values.iter().map(|v| OscType::Float(*v)).collect() };
+
+    Some(match mode {
+        EyeOutputMode::LeftRightPitchYaw => (
+            "/tracking/eye/LeftRightPitchYaw",
+            args(&[degrees(left), degrees(right)].concat()),
+        ),
+        EyeOutputMode::LeftRightVec => (
+            "/tracking/eye/LeftRightVec",
+            args(&[direction(left).to_array(), direction(right).to_array()].concat()),
+        ),
+        EyeOutputMode::CenterPitchYaw => ("/tracking/eye/CenterPitchYaw", args(&degrees(center))),
+        EyeOutputMode::CenterPitchYawDist => {
+            let [pitch, yaw] = degrees(center);
+            (
+                "/tracking/eye/CenterPitchYawDist",
+                args(&[pitch, yaw, distance()]),
+            )
+        }
+        EyeOutputMode::CenterVec => (
+            "/tracking/eye/CenterVec",
+            args(&direction(center).to_array()),
+        ),
+        EyeOutputMode::CenterVecFull => (
+            "/tracking/eye/CenterVecFull",
+            args(&(direction(center) * distance()).to_array()),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(args: &[OscType]) -> Vec<f32> {
+        args.iter()
+            .map(|a| match a {
+                OscType::Float(f) => *f,
+                other => panic!("not a float: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converging_eyes_set_the_focus_distance() {
+        // both eyes turned in towards a point half a meter ahead
+        let inward = (IPD_M / 2.).atan2(0.5);
+        let eyes = [
+            Some(Vec3::new(0., -inward, 0.)),
+            Some(Vec3::new(0., inward, 0.)),
+        ];
+
+        let (addr, args) = eye_message(EyeOutputMode::CenterPitchYawDist, eyes, 1.).unwrap();
+        assert_eq!(addr, "/tracking/eye/CenterPitchYawDist");
+        let [pitch, yaw, distance] = floats(&args)[..] else {
+            panic!("{:?}", args);
+        };
+        assert!(pitch.abs() < 1e-4 && yaw.abs() < 1e-4);
+        assert!((distance - 0.5).abs() < 1e-3, "distance {}", distance);
+
+        let (_, args) = eye_message(EyeOutputMode::CenterVecFull, eyes, 1.).unwrap();
+        let v = Vec3::from_slice(&floats(&args));
+        assert!((v - Vec3::new(0., 0., 0.5)).length() < 1e-3, "{}", v);
+
+        // one eye only falls back to the configured distance
+        let (_, args) = eye_message(EyeOutputMode::CenterVecFull, [eyes[0], None], 2.).unwrap();
+        assert!((Vec3::from_slice(&floats(&args)).length() - 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn vectors_use_vrchat_axes() {
+        // looking up and to the left
+        let eye = Vec3::new(0.3, 0.4, 0.);
+        let (_, args) = eye_message(EyeOutputMode::LeftRightVec, [Some(eye), None], 1.).unwrap();
+        let v = floats(&args);
+        assert_eq!(v.len(), 6);
+        assert!(v[0] < 0. && v[1] > 0. && v[2] > 0.);
+        assert!((Vec3::from_slice(&v[..3]).length() - 1.).abs() < 1e-5);
+        assert_eq!(v[..3], v[3..]);
+
+        let (_, args) = eye_message(EyeOutputMode::CenterPitchYaw, [Some(eye), None], 1.).unwrap();
+        assert_eq!(floats(&args), [-0.3f32.to_degrees(), -0.4f32.to_degrees()]);
+
+        assert!(eye_message(EyeOutputMode::CenterVec, [None, Some(eye)], 1.).is_none());
+    }
+}
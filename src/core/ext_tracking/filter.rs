@@ -0,0 +1,238 @@
+use std::{array, f32::consts::PI, ops::RangeInclusive};
+
+use strum::EnumCount;
+
+use crate::core::config::{EmaParams, FilterConfig, OneEuroParams, ShapeFilter};
+
+use super::unified::{UnifiedExpressions, UnifiedTrackingData};
+
+/// Smooths a single value over time. `dt` is in seconds.
+#[derive(Debug, Clone)]
+enum Filter {
+    Ema {
+        params: EmaParams,
+        value: Option<f32>,
+    },
+    OneEuro {
+        params: OneEuroParams,
+        value: Option<f32>,
+        derivative: f32,
+    },
+}
+
+impl Filter {
+    fn new(filter: &ShapeFilter) -> Option<Self> {
+        match filter {
+            ShapeFilter::None => None,
+            ShapeFilter::Ema(params) => Some(Filter::Ema {
+                params: *params,
+                value: None,
+            }),
+            ShapeFilter::OneEuro(params) => Some(Filter::OneEuro {
+                params: *params,
+                value: None,
+                derivative: 0.,
+            }),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Filter::Ema { value, .. } => *value = None,
+            Filter::OneEuro {
+                value, derivative, ..
+            } => {
+                *value = None;
+                *derivative = 0.;
+            }
+        }
+    }
+
+    fn filter(&mut self, x: f32, dt: f32) -> f32 {
+        match self {
+            Filter::Ema { params, value } => {
+                let out = match *value {
+                    // the time constant makes the result independent of the frame rate
+                    Some(prev) => lerp(prev, x, 1. - (-dt * 1000. / params.time_constant_ms).exp()),
+                    None => x,
+                };
+                *value = Some(out);
+                out
+            }
+            Filter::OneEuro {
+                params,
+                value,
+                derivative,
+            } => {
+                let out = match *value {
+                    Some(prev) if dt > 0. => {
+                        let dx = (x - prev) / dt;
+                        *derivative = lerp(*derivative, dx, smoothing(params.d_cutoff, dt));
+                        let cutoff = params.min_cutoff + params.beta * derivative.abs();
+                        lerp(prev, x, smoothing(cutoff, dt))
+                    }
+                    Some(prev) => prev,
+                    None => x,
+                };
+                *value = Some(out);
+                out
+            }
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Weight of the new sample for a low-pass filter at `cutoff` Hz.
+fn smoothing(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1. / (2. * PI * cutoff);
+    1. / (1. + tau / dt)
+}
+
+/// Per-shape filters, applied after calibration and before combined shapes are computed.
+pub struct Filters {
+    shapes: Vec<Option<Filter>>,
+    /// Pitch, yaw and roll of each eye
+    eyes: [[Option<Filter>; 3]; 2],
+}
+
+impl Filters {
+    pub fn new(config: &FilterConfig) -> Self {
+        use UnifiedExpressions as U;
+
+        let groups: [(RangeInclusive<usize>, &ShapeFilter); 5] = [
+            (U::EyeLeftX as usize..=U::EyeY as usize, &config.gaze),
+            (
+                U::EyeClosedRight as usize..=U::EyeWideLeft as usize,
+                &config.eyelids,
+            ),
+            (
+                U::BrowPinchRight as usize..=U::BrowOuterUpLeft as usize,
+                &config.brows,
+            ),
+            (
+                U::NasalDilationRight as usize..=U::MouthTightenerLeft as usize,
+                &config.mouth,
+            ),
+            (
+                U::TongueOut as usize..=U::TongueTwistLeft as usize,
+                &config.tongue,
+            ),
+        ];
+
+        let mut shapes = vec![None; UnifiedExpressions::COUNT];
+        for (range, filter) in groups {
+            for shape in &mut shapes[range] {
+                *shape = Filter::new(filter);
+            }
+        }
+
+        Self {
+            shapes,
+            eyes: array::from_fn(|_| array::from_fn(|_| Filter::new(&config.gaze))),
+        }
+    }
+
+    pub fn apply(&mut self, data: &mut UnifiedTrackingData, dt: f32) {
+        for (value, filter) in data.shapes.iter_mut().zip(self.shapes.iter_mut()) {
+            if let Some(filter) = filter.as_mut() {
+                *value = filter.filter(*value, dt);
+            }
+        }
+
+        for (eye, filters) in data.eyes.iter_mut().zip(self.eyes.iter_mut()) {
+            let filters = filters.iter_mut().flatten();
+            match eye.as_mut() {
+                Some(eye) => {
+                    for (value, filter) in [&mut eye.x, &mut eye.y, &mut eye.z]
+                        .into_iter()
+                        .zip(filters)
+                    {
+                        *value = filter.filter(*value, dt);
+                    }
+                }
+                // don't slide in from wherever the eye was when tracking comes back
+                None => filters.for_each(Filter::reset),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(filter: &mut Filter, x: f32, dt: f32, frames: usize) -> f32 {
+        (0..frames).fold(0., |_, _| filter.filter(x, dt))
+    }
+
+    #[test]
+    fn ema_does_not_depend_on_frame_rate() {
+        let params = ShapeFilter::Ema(EmaParams {
+            time_constant_ms: 50.,
+        });
+        let mut slow = Filter::new(&params).unwrap();
+        let mut fast = Filter::new(&params).unwrap();
+        slow.filter(0., 0.011);
+        fast.filter(0., 0.011);
+
+        // 100 ms of a step, at 90 and 360 frames per second
+        let slow = step(&mut slow, 1., 1. / 90., 9);
+        let fast = step(&mut fast, 1., 1. / 360., 36);
+        assert!((slow - fast).abs() < 1e-4, "{} != {}", slow, fast);
+        assert!((slow - (1. - (-2f32).exp())).abs() < 1e-4);
+    }
+
+    #[test]
+    fn one_euro_smooths_noise_but_follows_motion() {
+        let params = ShapeFilter::OneEuro(OneEuroParams {
+            min_cutoff: 1.,
+            beta: 1.,
+            d_cutoff: 1.,
+        });
+        let mut filter = Filter::new(&params).unwrap();
+        let dt = 0.011;
+
+        filter.filter(0.5, dt);
+        let noisy = (0..20)
+            .map(|i| filter.filter(if i % 2 == 0 { 0.52 } else { 0.48 }, dt))
+            .fold(0f32, |max, v| max.max((v - 0.5).abs()));
+        assert!(noisy < 0.01, "jitter of {} got through", noisy);
+
+        // a fast mouth opening is not held back for long
+        let open = step(&mut filter, 1., dt, 15);
+        assert!(open > 0.9, "only reached {}", open);
+    }
+
+    #[test]
+    fn applies_per_group_and_resets_lost_eyes() {
+        let config = FilterConfig {
+            mouth: ShapeFilter::Ema(EmaParams {
+                time_constant_ms: 100.,
+            }),
+            gaze: ShapeFilter::Ema(EmaParams {
+                time_constant_ms: 100.,
+            }),
+            ..Default::default()
+        };
+        let mut filters = Filters::new(&config);
+        let mut data = UnifiedTrackingData::default();
+        filters.apply(&mut data, 0.011);
+
+        data.setu(UnifiedExpressions::JawOpen, 1.);
+        data.setu(UnifiedExpressions::BrowLowererLeft, 1.);
+        data.eyes[0] = Some(glam::Vec3::new(0.2, 0.4, 0.));
+        filters.apply(&mut data, 0.011);
+
+        assert!(data.getu(UnifiedExpressions::JawOpen) < 0.5);
+        assert_eq!(data.getu(UnifiedExpressions::BrowLowererLeft), 1.);
+        // the first value after tracking comes back is taken as is
+        assert_eq!(data.eyes[0], Some(glam::Vec3::new(0.2, 0.4, 0.)));
+
+        data.eyes[0] = Some(glam::Vec3::ZERO);
+        filters.apply(&mut data, 0.011);
+        assert!(data.eyes[0].unwrap().y > 0.2);
+    }
+}
@@ -0,0 +1,638 @@
+use std::{collections::HashMap, fs, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Context};
+use rosc::{OscBundle, OscType};
+
+use crate::core::{bundle::AvatarBundle, config::FormulasConfig, AvatarParameters};
+
+use super::unified::{CombinedExpression, UnifiedExpressions, UnifiedTrackingData};
+
+/// The formulas that used to be hard-coded, see the file for the syntax.
+pub const BUILTIN: &str = include_str!("formulas.txt");
+
+/// Values that are not shapes or parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Builtin {
+    Dt,
+    LeftEyePitch,
+    LeftEyeYaw,
+    RightEyePitch,
+    RightEyeYaw,
+}
+
+impl Builtin {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "dt" => Builtin::Dt,
+            "left_eye_pitch" => Builtin::LeftEyePitch,
+            "left_eye_yaw" => Builtin::LeftEyeYaw,
+            "right_eye_pitch" => Builtin::RightEyePitch,
+            "right_eye_yaw" => Builtin::RightEyeYaw,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    If,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<(Self, usize)> {
+        Some(match name {
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            "clamp" => (Func::Clamp, 3),
+            "abs" => (Func::Abs, 1),
+            "if" => (Func::If, 3),
+            _ => return None,
+        })
+    }
+}
+
+/// Which avatar parameter types a `param` function reads, the others give its default
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParamKind {
+    Any,
+    Bool,
+    Float,
+}
+
+impl ParamKind {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "param" => ParamKind::Any,
+            "param_bool" => ParamKind::Bool,
+            "param_float" => ParamKind::Float,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f32),
+    Shape(usize),
+    Local(usize),
+    Output(usize),
+    Builtin(Builtin),
+    /// An avatar parameter, or the default while VRChat hasn't sent it
+    Param(Arc<str>, ParamKind, f32),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Shape(usize),
+    Local(usize),
+    Output(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Name(String),
+    Str(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 17] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "<", ">", "!", "=", "(", ")", ",",
+];
+
+fn tokenize(line: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while let Some(c) = line[pos..].chars().next() {
+        let rest = &line[pos..];
+        let word_len = |is_part: fn(char) -> bool| rest.find(|c| !is_part(c)).unwrap_or(rest.len());
+
+        pos += if c.is_whitespace() {
+            c.len_utf8()
+        } else if c == '#' {
+            break;
+        } else if c.is_ascii_digit() || c == '.' {
+            let len = word_len(|c| c.is_ascii_digit() || c == '.');
+            let num = &rest[..len];
+            tokens.push(Token::Num(
+                num.parse().with_context(|| format!("bad number {}", num))?,
+            ));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = word_len(|c| c.is_ascii_alphanumeric() || c == '_');
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else if c == '"' {
+            let len = rest[1..].find('"').context("unterminated string")?;
+            tokens.push(Token::Str(rest[1..=len].to_string()));
+            len + 2
+        } else if let Some(&op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            bail!("unexpected {:?}", c);
+        };
+    }
+    Ok(tokens)
+}
+
+/// Names known so far. Names have to be assigned before they can be used.
+#[derive(Default)]
+struct Scope {
+    locals: HashMap<String, usize>,
+    outputs: HashMap<String, usize>,
+    output_names: Vec<Arc<str>>,
+}
+
+impl Scope {
+    fn lookup(&self, name: &str) -> Option<Expr> {
+        if let Some(&i) = self.locals.get(name) {
+            return Some(Expr::Local(i));
+        }
+        if let Some(builtin) = Builtin::from_name(name) {
+            return Some(Expr::Builtin(builtin));
+        }
+        if let Some(shape) = shape_index(name) {
+            return Some(Expr::Shape(shape));
+        }
+        self.outputs.get(name).map(|&i| Expr::Output(i))
+    }
+}
+
+fn shape_index(name: &str) -> Option<usize> {
+    UnifiedExpressions::from_str(name)
+        .map(|e| e as usize)
+        .or_else(|_| CombinedExpression::from_str(name).map(|e| e as usize))
+        .ok()
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    scope: &'a Scope,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    fn expect(&mut self, op: &'static str) -> anyhow::Result<()> {
+        match self.next() {
+            Some(Token::Op(o)) if *o == op => Ok(()),
+            Some(token) => bail!("expected {} but found {:?}", op, token),
+            None => bail!("expected {} at the end of the line", op),
+        }
+    }
+
+    /// Parses operators whose precedence is at least `level`.
+    fn binary(&mut self, level: usize) -> anyhow::Result<Expr> {
+        const LEVELS: [&[(&str, BinOp)]; 5] = [
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[
+                ("<", BinOp::Lt),
+                ("<=", BinOp::Le),
+                (">", BinOp::Gt),
+                (">=", BinOp::Ge),
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+            ],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div)],
+        ];
+
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&(_, op)) = self
+            .peek_op()
+            .and_then(|next| ops.iter().find(|(o, _)| *o == next))
+        {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        match self.next().cloned() {
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Num(num)) => Ok(Expr::Num(num)),
+            Some(Token::Name(name)) if self.peek_op() == Some("(") => self.call(&name),
+            Some(Token::Name(name)) => self
+                .scope
+                .lookup(&name)
+                .with_context(|| format!("unknown name {}", name)),
+            Some(token) => bail!("unexpected {:?}", token),
+            None => bail!("unexpected end of the line"),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> anyhow::Result<Expr> {
+        self.expect("(")?;
+
+        if let Some(kind) = ParamKind::from_name(name) {
+            let Some(Token::Str(param)) = self.next().cloned() else {
+                bail!("{}() takes a parameter name in quotes", name);
+            };
+            self.expect(",")?;
+            let sign = if self.peek_op() == Some("-") {
+                self.pos += 1;
+                -1.
+            } else {
+                1.
+            };
+            let Some(&Token::Num(default)) = self.next() else {
+                bail!("the default of {}() must be a number", name);
+            };
+            self.expect(")")?;
+            return Ok(Expr::Param(param.into(), kind, sign * default));
+        }
+
+        let (func, num_args) =
+            Func::from_name(name).with_context(|| format!("unknown function {}", name))?;
+        let mut args = Vec::with_capacity(num_args);
+        for i in 0..num_args {
+            if i > 0 {
+                self.expect(",")?;
+            }
+            args.push(self.binary(0)?);
+        }
+        self.expect(")")?;
+        Ok(Expr::Call(func, args))
+    }
+}
+
+struct Statement {
+    target: Target,
+    expr: Expr,
+}
+
+/// Everything an expression can read.
+struct Inputs<'a> {
+    locals: &'a [f32],
+    outputs: &'a [f32],
+    data: &'a UnifiedTrackingData,
+    params: &'a AvatarParameters,
+    delta_t: f32,
+}
+
+fn truth(value: bool) -> f32 {
+    if value {
+        1.
+    } else {
+        0.
+    }
+}
+
+impl Expr {
+    fn eval(&self, inputs: &Inputs) -> f32 {
+        match self {
+            Expr::Num(num) => *num,
+            Expr::Shape(i) => inputs.data.shapes[*i],
+            Expr::Local(i) => inputs.locals[*i],
+            Expr::Output(i) => inputs.outputs[*i],
+            Expr::Builtin(builtin) => {
+                let eye = |i: usize| inputs.data.eyes[i].unwrap_or_default();
+                match builtin {
+                    Builtin::Dt => inputs.delta_t,
+                    Builtin::LeftEyePitch => eye(0).x,
+                    Builtin::LeftEyeYaw => eye(0).y,
+                    Builtin::RightEyePitch => eye(1).x,
+                    Builtin::RightEyeYaw => eye(1).y,
+                }
+            }
+            Expr::Param(name, kind, default) => match (kind, inputs.params.get(name)) {
+                (ParamKind::Any | ParamKind::Float, Some(OscType::Float(f))) => *f,
+                (ParamKind::Any, Some(OscType::Int(i))) => *i as f32,
+                (ParamKind::Any | ParamKind::Bool, Some(OscType::Bool(b))) => truth(*b),
+                _ => *default,
+            },
+            Expr::Neg(expr) => -expr.eval(inputs),
+            Expr::Not(expr) => truth(expr.eval(inputs) == 0.),
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(inputs), rhs.eval(inputs));
+                match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Lt => truth(a < b),
+                    BinOp::Le => truth(a <= b),
+                    BinOp::Gt => truth(a > b),
+                    BinOp::Ge => truth(a >= b),
+                    BinOp::Eq => truth(a == b),
+                    BinOp::Ne => truth(a != b),
+                    BinOp::And => truth(a != 0. && b != 0.),
+                    BinOp::Or => truth(a != 0. || b != 0.),
+                }
+            }
+            Expr::Call(func, args) => {
+                let arg = |i: usize| args[i].eval(inputs);
+                match func {
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    // unlike f32::clamp, this doesn't panic on a bad range
+                    Func::Clamp => arg(0).max(arg(1)).min(arg(2)),
+                    Func::Abs => arg(0).abs(),
+                    Func::If if arg(0) != 0. => arg(1),
+                    Func::If => arg(2),
+                }
+            }
+        }
+    }
+}
+
+/// Computes combined expressions and custom parameters from the unified shapes.
+pub struct Formulas {
+    statements: Vec<Statement>,
+    locals: Vec<f32>,
+    /// Custom parameters
+    outputs: Vec<f32>,
+    output_names: Vec<Arc<str>>,
+    /// The value of each custom parameter that was last sent to VRChat
+    sent: Vec<Option<f32>>,
+}
+
+impl Formulas {
+    pub fn parse(sources: &[(&str, &str)]) -> anyhow::Result<Self> {
+        let mut scope = Scope::default();
+        let mut statements = Vec::new();
+
+        for (source, text) in sources {
+            for (i, line) in text.lines().enumerate() {
+                let statement = parse_statement(line, &mut scope)
+                    .with_context(|| format!("{} line {}", source, i + 1))?;
+                statements.extend(statement);
+            }
+        }
+
+        Ok(Self {
+            statements,
+            locals: vec![0.; scope.locals.len()],
+            outputs: vec![0.; scope.output_names.len()],
+            sent: vec![None; scope.output_names.len()],
+            output_names: scope.output_names,
+        })
+    }
+
+    /// The built-in formulas, then those from `config.file`.
+    pub fn load(config: &FormulasConfig) -> anyhow::Result<Self> {
+        let mut sources = Vec::new();
+        if config.builtin {
+            sources.push(("built-in formulas".to_string(), BUILTIN.to_string()));
+        }
+        if let Some(file) = config.file.as_ref() {
+            let text =
+                fs::read_to_string(file).with_context(|| format!("Could not read {}", file))?;
+            sources.push((file.clone(), text));
+        }
+
+        let sources: Vec<(&str, &str)> = sources
+            .iter()
+            .map(|(source, text)| (source.as_str(), text.as_str()))
+            .collect();
+        Self::parse(&sources)
+    }
+
+    /// Runs every formula in order. Values that are read before they are assigned
+    /// still hold what they were set to on the last frame.
+    pub fn step(
+        &mut self,
+        data: &mut UnifiedTrackingData,
+        params: &AvatarParameters,
+        delta_t: f32,
+    ) {
+        for statement in self.statements.iter() {
+            let value = statement.expr.eval(&Inputs {
+                locals: &self.locals,
+                outputs: &self.outputs,
+                data,
+                params,
+                delta_t,
+            });
+            match statement.target {
+                Target::Shape(i) => data.shapes[i] = value,
+                Target::Local(i) => self.locals[i] = value,
+                Target::Output(i) => self.outputs[i] = value,
+            }
+        }
+    }
+
+    /// Sends the custom parameters that changed by more than `deadband`.
+    pub fn apply_to_bundle(&mut self, deadband: f32, bundle: &mut OscBundle) {
+        for ((name, value), sent) in self
+            .output_names
+            .iter()
+            .zip(self.outputs.iter())
+            .zip(self.sent.iter_mut())
+        {
+            if sent.is_none_or(|sent| (value - sent).abs() > deadband) {
+                bundle.send_parameter(name, OscType::Float(*value));
+                *sent = Some(*value);
+            }
+        }
+    }
+}
+
+fn parse_statement(line: &str, scope: &mut Scope) -> anyhow::Result<Option<Statement>> {
+    let mut tokens = tokenize(line)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let keyword = match &tokens[0] {
+        Token::Name(word) if word == "let" || word == "param" => Some(word.clone()),
+        _ => None,
+    };
+    if keyword.is_some() {
+        tokens.remove(0);
+    }
+    let (Some(Token::Name(name)), Some(Token::Op("="))) = (tokens.first(), tokens.get(1)) else {
+        bail!("expected NAME = EXPRESSION");
+    };
+    let name = name.clone();
+
+    let is_free = scope.lookup(&name).is_none()
+        && Func::from_name(&name).is_none()
+        && ParamKind::from_name(&name).is_none();
+
+    // a new parameter can build on its own value from the last frame, a temporary can't
+    let target = match (keyword.as_deref(), scope.lookup(&name)) {
+        (Some("let"), _) if is_free => None,
+        (Some(_), _) if is_free => {
+            let i = scope.output_names.len();
+            scope.output_names.push(name.as_str().into());
+            scope.outputs.insert(name.clone(), i);
+            Some(Target::Output(i))
+        }
+        (Some(_), _) => bail!("{} is already defined", name),
+        (None, Some(Expr::Local(i))) => Some(Target::Local(i)),
+        (None, Some(Expr::Shape(i))) => Some(Target::Shape(i)),
+        (None, Some(Expr::Output(i))) => Some(Target::Output(i)),
+        (None, Some(_)) => bail!("{} can't be assigned to", name),
+        (None, None) => bail!(
+            "unknown name {}, a new parameter is declared with `param {} = ...`",
+            name,
+            name
+        ),
+    };
+
+    let mut parser = Parser {
+        tokens: &tokens[2..],
+        pos: 0,
+        scope,
+    };
+    let expr = parser.binary(0)?;
+    if let Some(token) = parser.next() {
+        bail!("unexpected {:?}", token);
+    }
+
+    let target = target.unwrap_or_else(|| {
+        let i = scope.locals.len();
+        scope.locals.insert(name, i);
+        Target::Local(i)
+    });
+
+    Ok(Some(Statement { target, expr }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formulas(text: &str) -> anyhow::Result<Formulas> {
+        Formulas::parse(&[("test", text)])
+    }
+
+    #[test]
+    fn builtin_formulas_compute_combined_shapes() {
+        let mut formulas = formulas(BUILTIN).unwrap();
+        let mut data = UnifiedTrackingData::default();
+        data.setu(UnifiedExpressions::EyeClosedLeft, 0.2);
+        data.setu(UnifiedExpressions::EyeWideLeft, 0.4);
+        data.setu(UnifiedExpressions::JawRight, 0.3);
+        data.setu(UnifiedExpressions::JawLeft, 0.5);
+
+        formulas.step(&mut data, &AvatarParameters::new(), 0.011);
+        assert!((data.getc(CombinedExpression::EyeLidLeft) - 0.595).abs() < 1e-6);
+        assert!((data.getc(CombinedExpression::JawX) + 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parameters_and_state() {
+        let mut formulas = formulas(
+            "let on = param(\"Toggle\", 0) && !(left_eye_yaw < -0.5)\n\
+             param Timer = clamp(Timer + if(on, 1, -1) * dt, 0, 2) # seconds\n",
+        )
+        .unwrap();
+        let mut data = UnifiedTrackingData::default();
+        let params: AvatarParameters = [("Toggle".into(), OscType::Bool(true))]
+            .into_iter()
+            .collect();
+
+        for _ in 0..3 {
+            formulas.step(&mut data, &params, 0.5);
+        }
+        let mut bundle = OscBundle::new_bundle();
+        formulas.apply_to_bundle(0.01, &mut bundle);
+        formulas.apply_to_bundle(0.01, &mut bundle);
+        assert_eq!(bundle.content.len(), 1);
+        assert_eq!(formulas.outputs, vec![1.5]);
+
+        data.eyes[0] = Some(glam::Vec3::new(0., -1., 0.));
+        formulas.step(&mut data, &params, 0.5);
+        assert_eq!(formulas.outputs, vec![1.]);
+    }
+
+    #[test]
+    fn blush_reads_only_the_parameter_types_it_always_did() {
+        let mut formulas = formulas(BUILTIN).unwrap();
+        let blush_face = ("BlushFace", OscType::Float(1.));
+        for (params, expected) in [
+            (vec![blush_face.clone()], 0.1),
+            (vec![("BlushFace", OscType::Int(1))], 0.),
+            (vec![("BlushNade", OscType::Bool(true))], 0.),
+            (
+                vec![("AllowBlush", OscType::Int(0)), blush_face.clone()],
+                0.1,
+            ),
+            (vec![("AllowBlush", OscType::Bool(false)), blush_face], 0.),
+        ] {
+            let params: AvatarParameters = params
+                .iter()
+                .map(|(name, value)| ((*name).into(), value.clone()))
+                .collect();
+            let mut data = UnifiedTrackingData::default();
+            formulas.step(&mut data, &params, 1.);
+            assert_eq!(
+                data.getc(CombinedExpression::Blush),
+                expected,
+                "{:?}",
+                params
+            );
+        }
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        for (text, error) in [
+            ("JawX = JawRigth", "test line 1: unknown name JawRigth"),
+            (
+                "\nlet a = 1\nlet a = 2",
+                "test line 3: a is already defined",
+            ),
+            ("let a = a + 1", "unknown name a"),
+            ("dt = 1", "dt can't be assigned to"),
+            ("Foo = 1", "unknown name Foo, a new parameter is declared"),
+            ("param JawX = 1", "JawX is already defined"),
+            ("param Foo = min(1)", "expected , but found Op(\")\")"),
+            ("param Foo = 1 2", "unexpected Num(2.0)"),
+        ] {
+            let e = format!("{:#}", formulas(text).err().unwrap());
+            assert!(e.contains(error), "{:?}: {}", text, e);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use colored::{Color, Colorize};
+use glam::{Affine3A, EulerRot, Quat, Vec3};
+use once_cell::sync::Lazy;
+use strum::EnumCount;
+
+use crate::core::{AppState, INSTRUCTIONS_END, INSTRUCTIONS_START, TRACK_ON};
+
+use super::{
+    arkit::{arkit_to_unified, ArkitShape, ArkitShapes, LIVE_LINK_ANGLE_RAD},
+    bind_udp, is_timeout,
+    packet::PacketReader,
+    region::{Region, RegionTimes},
+    unified::{UnifiedExpressions, UnifiedTrackingData},
+    FaceReceiver, LoopGuard, LoopHandle,
+};
+
+static STA_ON: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "LLINK".color(Color::Green)).into());
+static STA_OFF: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "LLINK".color(Color::Red)).into());
+
+/// Length of the device id, a UUID followed by a `$`
+const DEVICE_ID_LEN: usize = 37;
+
+/// One UDP packet from the Live Link Face app.
+///
+/// Layout: version (i32 LE), device id, then big-endian: name length (i32), name,
+/// frame (i32), subframe (f32), fps (i32), fps denominator (i32), value count (u8)
+/// and the values (f32 each). The count is 0 while no face is found.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct LiveLinkFrame {
+    pub name: String,
+    pub frame: i32,
+    pub values: Option<Box<ArkitShapes>>,
+}
+
+pub(super) fn decode(buf: &[u8]) -> anyhow::Result<LiveLinkFrame> {
+    let mut r = PacketReader::new(buf);
+
+    let version = i32::from_le_bytes(r.take()?);
+    if version != 6 {
+        bail!("Unsupported Live Link version {}", version);
+    }
+    r.skip(DEVICE_ID_LEN)?;
+
+    let name_len = i32::from_be_bytes(r.take()?);
+    let name = usize::try_from(name_len)
+        .ok()
+        .and_then(|len| r.bytes(len).ok())
+        .with_context(|| format!("Bad subject name length {}", name_len))?;
+    let name = String::from_utf8_lossy(name).into_owned();
+
+    let frame = i32::from_be_bytes(r.take()?);
+    r.skip(12)?; // subframe, fps, fps denominator
+    let [count] = r.take()?;
+
+    let values = match count as usize {
+        0 => None,
+        ArkitShape::COUNT => {
+            let mut values = Box::new([0f32; ArkitShape::COUNT]);
+            for value in values.iter_mut() {
+                *value = f32::from_be_bytes(r.take()?);
+            }
+            Some(values)
+        }
+        count => bail!("Expected {} values, got {}", ArkitShape::COUNT, count),
+    };
+
+    Ok(LiveLinkFrame {
+        name,
+        frame,
+        values,
+    })
+}
+
+fn live_link_angle(values: &ArkitShapes, shape: ArkitShape) -> f32 {
+    values[shape as usize] * LIVE_LINK_ANGLE_RAD
+}
+
+pub(super) struct LiveLinkReceiver {
+    listen_port: u16,
+    sender: SyncSender<Box<ArkitShapes>>,
+    receiver: Receiver<Box<ArkitShapes>>,
+    last_received: Option<Instant>,
+    guard: LoopGuard,
+}
+
+impl LiveLinkReceiver {
+    pub fn new(listen_port: u16) -> Self {
+        let (sender, receiver) = sync_channel(8);
+        Self {
+            listen_port,
+            sender,
+            receiver,
+            last_received: None,
+            guard: LoopGuard::default(),
+        }
+    }
+}
+
+impl FaceReceiver for LiveLinkReceiver {
+    fn start_loop(&mut self) {
+        log::info!("{}", *INSTRUCTIONS_START);
+        log::info!("");
+        log::info!("Selected Live Link Face to provide face data.");
+        log::info!("");
+        log::info!("In the Live Link Face app:");
+        log::info!(
+            "• Settings → Live Link: add this computer's IP address, port {}",
+            format!("{}", self.listen_port).color(Color::Cyan)
+        );
+        log::info!(
+            "• Set {} to {}",
+            "Protocol".color(Color::BrightYellow),
+            "Live Link (ARKit)".color(Color::Cyan)
+        );
+        log::info!("• Make sure the phone and this computer are on the same network.");
+        log::info!("");
+        log::info!("Status bar tickers:");
+        log::info!("• {} → face data is being received", *STA_ON);
+        log::info!("• {} → head data is being received", *TRACK_ON);
+        log::info!("");
+        log::info!("{}", *INSTRUCTIONS_END);
+
+        let listen_port = self.listen_port;
+        let sender = self.sender.clone();
+        let handle = self.guard.handle();
+        thread::spawn(move || live_link_loop(listen_port, sender, handle));
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        if let Some(values) = self.receiver.try_iter().last() {
+            if let Some(shapes) = arkit_to_unified(&values[..]) {
+                data.shapes[..UnifiedExpressions::COUNT]
+                    .copy_from_slice(&shapes[..UnifiedExpressions::COUNT]);
+            }
+
+            for (eye, [yaw, pitch, roll]) in [
+                [
+                    ArkitShape::LeftEyeYaw,
+                    ArkitShape::LeftEyePitch,
+                    ArkitShape::LeftEyeRoll,
+                ],
+                [
+                    ArkitShape::RightEyeYaw,
+                    ArkitShape::RightEyePitch,
+                    ArkitShape::RightEyeRoll,
+                ],
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                data.eyes[eye] = Some(Vec3::new(
+                    live_link_angle(&values, pitch),
+                    live_link_angle(&values, yaw),
+                    live_link_angle(&values, roll),
+                ));
+            }
+
+            let rotation = Quat::from_euler(
+                EulerRot::YXZ,
+                live_link_angle(&values, ArkitShape::HeadYaw),
+                live_link_angle(&values, ArkitShape::HeadPitch),
+                live_link_angle(&values, ArkitShape::HeadRoll),
+            );
+            let (_, _, translation) = state.tracking.head.to_scale_rotation_translation();
+            state.tracking.head = Affine3A::from_rotation_translation(rotation, translation);
+            state.tracking.last_received = Instant::now();
+            self.last_received = Some(Instant::now());
+        }
+
+        if self
+            .last_received
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
+            state.status.add_item(STA_ON.clone());
+        } else {
+            state.status.add_item(STA_OFF.clone());
+        }
+    }
+
+    fn last_update(&self) -> RegionTimes {
+        [self.last_received; Region::COUNT]
+    }
+}
+
+fn live_link_loop(listen_port: u16, sender: SyncSender<Box<ArkitShapes>>, handle: LoopHandle) {
+    // the phone is on the network, so this can't be localhost only
+    let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), listen_port);
+    let Some(listener) = bind_udp("Live Link", addr) else {
+        return;
+    };
+
+    let mut subject = None;
+    let mut buf = [0u8; 1024];
+    while !handle.is_stopped() {
+        let size = match listener.recv(&mut buf) {
+            Ok(size) => size,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                log::warn!("Live Link: failed to receive a packet: {}", e);
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let frame = match decode(&buf[..size]) {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::debug!("Live Link: {e:#}");
+                continue;
+            }
+        };
+        if subject.as_ref() != Some(&frame.name) {
+            log::info!("Live Link: receiving from {}", frame.name);
+            subject = Some(frame.name);
+        }
+        if let Some(values) = frame.values {
+            // a full channel means the main loop is behind, and a dropped frame won't be missed
+            let _ = sender.try_send(values);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hand-built header in Live Link Face's frame layout, not a captured packet:
+    /// a made-up device id, subject "iPhone", frame 4660, 60 fps.
+    #[rustfmt::skip]
+    const HEADER: &[u8] = &[
+        0x06, 0x00, 0x00, 0x00,
+        // device id
+        b'4', b'F', b'3', b'A', b'2', b'C', b'1', b'B', b'-', b'9', b'D', b'8', b'E', b'-',
+        b'4', b'7', b'6', b'5', b'-', b'A', b'3', b'B', b'2', b'-', b'C', b'1', b'D', b'0',
+        b'E', b'9', b'F', b'8', b'A', b'7', b'B', b'6', b'$',
+        0x00, 0x00, 0x00, 0x06, b'i', b'P', b'h', b'o', b'n', b'e',
+        // frame, subframe, fps, denominator
+        0x00, 0x00, 0x12, 0x34,
+        0x3e, 0x80, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x3c,
+        0x00, 0x00, 0x00, 0x01,
+    ];
+
+    /// A whole datagram in Live Link Face's v6 layout: subject "iPhone", frame 123456.
+    /// No capture from the app was at hand, so it is written to that layout too;
+    /// a datagram saved from the app (e.g. with Wireshark) can replace it, along with the
+    /// expected values in `decodes_a_whole_datagram`.
+    const DATAGRAM_V6: &[u8] = include_bytes!("livelink_v6.bin");
+
+    fn frame(count: u8, values: &[f32]) -> Vec<u8> {
+        let mut buf = HEADER.to_vec();
+        buf.push(count);
+        for value in values.iter() {
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn decodes_blendshapes_and_head() {
+        let mut values = [0f32; ArkitShape::COUNT];
+        values[ArkitShape::EyeBlinkLeft as usize] = 0.9;
+        values[ArkitShape::JawOpen as usize] = 0.35;
+        values[ArkitShape::HeadYaw as usize] = -0.25;
+
+        let frame = decode(&frame(61, &values)).unwrap();
+        assert_eq!(frame.name, "iPhone");
+        assert_eq!(frame.frame, 0x1234);
+        let decoded = frame.values.unwrap();
+        assert_eq!(decoded[..], values[..]);
+
+        let shapes = arkit_to_unified(&decoded[..]).unwrap();
+        assert_eq!(shapes[UnifiedExpressions::EyeClosedLeft as usize], 0.9);
+        assert_eq!(shapes[UnifiedExpressions::JawOpen as usize], 0.35);
+    }
+
+    #[test]
+    fn decodes_a_whole_datagram() {
+        let frame = decode(DATAGRAM_V6).unwrap();
+        assert_eq!(frame.name, "iPhone");
+        assert_eq!(frame.frame, 123456);
+
+        let values = frame.values.unwrap();
+        assert_eq!(values[ArkitShape::EyeBlinkLeft as usize], 0.875);
+        assert_eq!(values[ArkitShape::EyeBlinkRight as usize], 0.8125);
+        assert_eq!(values[ArkitShape::JawOpen as usize], 0.375);
+        assert_eq!(values[ArkitShape::MouthSmileRight as usize], 0.25);
+        assert_eq!(values[ArkitShape::BrowInnerUp as usize], 0.125);
+        assert_eq!(values[ArkitShape::HeadYaw as usize], -0.125);
+        assert_eq!(values[ArkitShape::TongueOut as usize], 0.);
+    }
+
+    #[test]
+    fn no_face_is_not_an_error() {
+        let frame = decode(&frame(0, &[])).unwrap();
+        assert_eq!(frame.values, None);
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        let full = frame(61, &[0.5; ArkitShape::COUNT]);
+        assert!(decode(&full[..full.len() - 1]).is_err());
+        assert!(decode(&frame(52, &[0.5; 52])).is_err());
+        assert!(decode(b"garbage").is_err());
+    }
+}
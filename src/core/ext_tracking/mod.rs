@@ -1,4 +1,13 @@
-use std::{array, str::FromStr, sync::Arc};
+use std::{
+    array,
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
 
 use once_cell::sync::Lazy;
 use regex::Regex;
@@ -16,9 +25,25 @@ use self::babble::BabbleEtvrReceiver;
 #[cfg(feature = "openxr")]
 use self::openxr::OpenXrReceiver;
 
+use self::calibration::Calibration;
+use self::composite::CompositeReceiver;
+use self::curves::{Curves, ShapeCurve};
+use self::filter::Filters;
+use self::formulas::Formulas;
+use self::livelink::LiveLinkReceiver;
+use self::openseeface::OpenSeeFaceReceiver;
+use self::procedural::ProceduralReceiver;
+use self::recording::ReplayReceiver;
+use self::region::{Region, RegionTimes};
+use self::tracking_loss::TrackingLoss;
 use self::unified::{CombinedExpression, UnifiedExpressions, UnifiedTrackingData, NUM_SHAPES};
+use self::vmc::VmcReceiver;
 
 use super::{
+    config::{
+        BabbleConfig, CalibrationConfig, CompositorConfig, Config, EyeOutputConfig, FilterConfig,
+        FormulasConfig, TrackingLossConfig,
+    },
     ext_oscjson::{MysteryParam, OscJsonNode},
     AppState,
 };
@@ -28,19 +53,39 @@ use strum::IntoEnumIterator;
 
 #[cfg(feature = "alvr")]
 mod alvr;
+pub mod arkit;
 #[cfg(feature = "babble")]
 mod babble;
+mod calibration;
+mod composite;
+pub mod curves;
+mod eye_output;
 mod face2_fb;
+mod filter;
+mod formulas;
 #[cfg(feature = "openxr")]
 mod htc;
+mod livelink;
+mod openseeface;
 #[cfg(feature = "openxr")]
 mod openxr;
+mod packet;
+mod procedural;
+pub mod recording;
+mod region;
 mod sranipal;
+mod tracking_loss;
 pub mod unified;
+mod vmc;
 
 trait FaceReceiver {
     fn start_loop(&mut self);
     fn receive(&mut self, _data: &mut UnifiedTrackingData, _: &mut AppState);
+    /// When each face region last got data from the device.
+    /// `None` where the receiver can't tell, so tracking loss watches the values instead.
+    fn last_update(&self) -> RegionTimes {
+        [None; Region::COUNT]
+    }
 }
 
 struct DummyReceiver;
@@ -50,14 +95,127 @@ impl FaceReceiver for DummyReceiver {
     fn receive(&mut self, _data: &mut UnifiedTrackingData, _: &mut AppState) {}
 }
 
+/// Held by a receiver that does its work on another thread.
+/// The thread stops once the receiver is dropped, which frees its port for a replacement.
+#[derive(Default)]
+struct LoopGuard(Arc<()>);
+
+impl LoopGuard {
+    fn handle(&self) -> LoopHandle {
+        LoopHandle(Arc::downgrade(&self.0))
+    }
+}
+
+/// The thread's side of a [`LoopGuard`].
+struct LoopHandle(Weak<()>);
+
+impl LoopHandle {
+    fn is_stopped(&self) -> bool {
+        self.0.strong_count() == 0
+    }
+}
+
+/// How long a receiver's thread waits for a packet before checking whether to stop
+const LOOP_POLL: Duration = Duration::from_millis(100);
+
+/// Binds a receiver's UDP socket with a read timeout, so its loop can check its [`LoopHandle`].
+/// The receiver being replaced may hold the port for another `LOOP_POLL`, so binding is retried.
+fn bind_udp(name: &str, addr: SocketAddr) -> Option<UdpSocket> {
+    let mut attempts = 0;
+    let socket = loop {
+        match UdpSocket::bind(addr) {
+            Ok(socket) => break socket,
+            Err(e) if e.kind() == ErrorKind::AddrInUse && attempts < 5 => {
+                attempts += 1;
+                thread::sleep(LOOP_POLL);
+            }
+            Err(e) => {
+                log::error!("{}: could not listen on port {}: {}", name, addr.port(), e);
+                return None;
+            }
+        }
+    };
+    if let Err(e) = socket.set_read_timeout(Some(LOOP_POLL)) {
+        log::warn!("{}: could not set a read timeout: {}", name, e);
+    }
+    Some(socket)
+}
+
+/// Whether a receive error only means that nothing arrived within `LOOP_POLL`
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn create_receiver(setup: &FaceSetup, config: &Config) -> Box<dyn FaceReceiver> {
+    match setup {
+        FaceSetup::Dummy => Box::new(DummyReceiver {}),
+        #[cfg(feature = "alvr")]
+        FaceSetup::Alvr => Box::new(AlvrReceiver::new()),
+        #[cfg(feature = "openxr")]
+        FaceSetup::Openxr => Box::new(OpenXrReceiver::new()),
+        #[cfg(feature = "babble")]
+        FaceSetup::Babble { listen } => Box::new(BabbleEtvrReceiver::new(*listen, &config.babble)),
+        FaceSetup::LiveLink { listen } => Box::new(LiveLinkReceiver::new(*listen)),
+        FaceSetup::OpenSeeFace { listen } => Box::new(OpenSeeFaceReceiver::new(*listen)),
+        FaceSetup::Vmc { listen } => Box::new(VmcReceiver::new(*listen)),
+        FaceSetup::Procedural => Box::new(ProceduralReceiver::new()),
+        FaceSetup::Replay {
+            file,
+            speed,
+            looping,
+        } => Box::new(ReplayReceiver::new(file, *speed, *looping)),
+        FaceSetup::Composite => Box::new(CompositeReceiver::new(config)),
+    }
+}
+
+fn load_formulas(config: &FormulasConfig) -> Formulas {
+    Formulas::load(config).unwrap_or_else(|e| {
+        log::error!("{e:#}");
+        log::warn!("Using the built-in formulas only");
+        Formulas::load(&FormulasConfig::default()).expect("built-in formulas are valid")
+    })
+}
+
+fn load_calibration(config: &Config, config_dir: &str) -> Option<Calibration> {
+    config
+        .calibration
+        .enabled
+        .then(|| Calibration::load(config_dir, &config.calibration.profile))
+}
+
+fn create_tracking_loss(config: &TrackingLossConfig) -> Option<TrackingLoss> {
+    config.enabled.then(|| TrackingLoss::new(config))
+}
+
 pub struct ExtTracking {
+    /// What the receiver reports, before calibration
+    pub raw: UnifiedTrackingData,
     pub data: UnifiedTrackingData,
+    calibration: Option<Calibration>,
+    calibration_config: CalibrationConfig,
+    filters: Filters,
+    filter_config: FilterConfig,
+    tracking_loss: Option<TrackingLoss>,
+    tracking_loss_config: TrackingLossConfig,
+    curves: Curves,
+    curve_config: BTreeMap<String, ShapeCurve>,
+    formulas: Formulas,
+    formulas_config: FormulasConfig,
+    eye_output: EyeOutputConfig,
+    /// The current avatar, when VRChat has told us which one it is
+    avatar_id: Option<String>,
     params: [Option<MysteryParam>; NUM_SHAPES],
     receiver: Box<dyn FaceReceiver>,
+    face_config: FaceSetup,
+    babble_config: BabbleConfig,
+    compositor_config: CompositorConfig,
+    send_deadband: f32,
+    /// Where calibrations are kept
+    config_dir: Arc<str>,
 }
 
 impl ExtTracking {
-    pub fn new(setup: FaceSetup) -> Self {
+    pub fn new(config: &Config, config_dir: Arc<str>) -> Self {
         let default_combined = vec![
             CombinedExpression::BrowExpressionLeft,
             CombinedExpression::BrowExpressionRight,
@@ -114,20 +272,30 @@ impl ExtTracking {
             params[e as usize] = Some(new);
         }
 
-        let receiver: Box<dyn FaceReceiver> = match setup {
-            FaceSetup::Dummy => Box::new(DummyReceiver {}),
-            #[cfg(feature = "alvr")]
-            FaceSetup::Alvr => Box::new(AlvrReceiver::new()),
-            #[cfg(feature = "openxr")]
-            FaceSetup::Openxr => Box::new(OpenXrReceiver::new()),
-            #[cfg(feature = "babble")]
-            FaceSetup::Babble { listen } => Box::new(BabbleEtvrReceiver::new(listen)),
-        };
+        let receiver = create_receiver(&config.face, config);
 
         let mut me = Self {
+            raw: UnifiedTrackingData::default(),
             data: UnifiedTrackingData::default(),
+            calibration: load_calibration(config, &config_dir),
+            calibration_config: config.calibration.clone(),
+            filters: Filters::new(&config.filter),
+            filter_config: config.filter.clone(),
+            tracking_loss: create_tracking_loss(&config.tracking_loss),
+            tracking_loss_config: config.tracking_loss.clone(),
+            curves: Curves::new(&config.curves),
+            curve_config: config.curves.clone(),
+            formulas: load_formulas(&config.formulas),
+            formulas_config: config.formulas.clone(),
+            eye_output: config.eye_output.clone(),
+            avatar_id: None,
             params,
             receiver,
+            face_config: config.face.clone(),
+            babble_config: config.babble.clone(),
+            compositor_config: config.compositor.clone(),
+            send_deadband: config.tracking.send_deadband,
+            config_dir,
         };
 
         log::info!("--- Default params ---");
@@ -138,6 +306,50 @@ impl ExtTracking {
         me
     }
 
+    /// Applies a changed config, restarting the receiver if its settings changed.
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.send_deadband = config.tracking.send_deadband;
+        self.eye_output = config.eye_output.clone();
+        if config.face != self.face_config
+            || config.babble != self.babble_config
+            || config.compositor != self.compositor_config
+        {
+            log::info!("Restarting the face receiver");
+            // the old receiver is dropped here, so its thread lets go of the port
+            self.receiver = create_receiver(&config.face, config);
+            self.receiver.start_loop();
+            self.raw = UnifiedTrackingData::default();
+            self.face_config = config.face.clone();
+            self.babble_config = config.babble.clone();
+            self.compositor_config = config.compositor.clone();
+        }
+        if config.calibration != self.calibration_config {
+            self.calibration = load_calibration(config, &self.config_dir);
+            self.calibration_config = config.calibration.clone();
+        }
+        if config.filter != self.filter_config {
+            self.filters = Filters::new(&config.filter);
+            self.filter_config = config.filter.clone();
+        }
+        if config.tracking_loss != self.tracking_loss_config {
+            match self.tracking_loss.as_mut() {
+                Some(loss) if config.tracking_loss.enabled => {
+                    loss.reconfigure(&config.tracking_loss)
+                }
+                _ => self.tracking_loss = create_tracking_loss(&config.tracking_loss),
+            }
+            self.tracking_loss_config = config.tracking_loss.clone();
+        }
+        if config.curves != self.curve_config {
+            self.curves = Curves::new(&config.curves);
+            self.curve_config = config.curves.clone();
+        }
+        if config.formulas != self.formulas_config {
+            self.formulas = load_formulas(&config.formulas);
+            self.formulas_config = config.formulas.clone();
+        }
+    }
+
     pub fn step(&mut self, state: &mut AppState, bundle: &mut OscBundle) {
         let motion = matches!(state.params.get("Motion"), Some(OscType::Int(1)));
         let face_override = matches!(state.params.get("FaceFreeze"), Some(OscType::Bool(true)));
@@ -149,8 +361,27 @@ impl ExtTracking {
         } else if motion ^ face_override {
             log::debug!("Freeze");
         } else {
-            self.receiver.receive(&mut self.data, state);
-            self.data.calc_combined(state);
+            self.receiver.receive(&mut self.raw, state);
+            self.data.shapes[..UnifiedExpressions::COUNT]
+                .copy_from_slice(&self.raw.shapes[..UnifiedExpressions::COUNT]);
+            self.data.eyes = self.raw.eyes;
+            if let Some(calibration) = self.calibration.as_mut() {
+                calibration.step(&mut self.data, state);
+            }
+            self.filters.apply(&mut self.data, state.delta_t);
+            if let Some(loss) = self.tracking_loss.as_mut() {
+                let last_update = self.receiver.last_update();
+                loss.apply(
+                    &self.raw,
+                    last_update,
+                    &mut self.data,
+                    Instant::now(),
+                    state.delta_t,
+                );
+            }
+            self.curves.apply(&mut self.data);
+            self.formulas
+                .step(&mut self.data, &state.params, state.delta_t);
         }
 
         if matches!(state.params.get("FacePause"), Some(OscType::Bool(true))) {
@@ -158,7 +389,22 @@ impl ExtTracking {
             return;
         }
 
-        self.data.apply_to_bundle(&mut self.params, bundle);
+        self.data.apply_to_bundle(
+            &mut self.params,
+            self.send_deadband,
+            self.eye_output.mode_for(self.avatar_id.as_deref()),
+            self.eye_output.focus_distance_m,
+            bundle,
+        );
+        self.formulas.apply_to_bundle(self.send_deadband, bundle);
+    }
+
+    pub fn set_avatar_id(&mut self, id: &str) {
+        self.avatar_id = Some(id.into());
+        let mode = self.eye_output.mode_for(Some(id));
+        if mode != self.eye_output.mode {
+            log::info!("Eye output for this avatar: {:?}", mode);
+        }
     }
 
     pub fn osc_json(&mut self, avatar_node: &OscJsonNode) {
@@ -265,3 +511,36 @@ impl ExtTracking {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn dropped_receivers_free_their_port() {
+        let addr = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|s| s.local_addr())
+            .unwrap();
+
+        let guard = LoopGuard::default();
+        let handle = guard.handle();
+        let listener = thread::spawn(move || {
+            let socket = bind_udp("test", addr).unwrap();
+            let mut buf = [0u8; 16];
+            while !handle.is_stopped() {
+                if let Err(e) = socket.recv(&mut buf) {
+                    assert!(is_timeout(&e));
+                }
+            }
+        });
+
+        // wait for the first socket to be bound, then replace it
+        while UdpSocket::bind(addr).is_ok() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(guard);
+        assert!(bind_udp("test", addr).is_some());
+        listener.join().unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use colored::{Color, Colorize};
+use glam::{Affine3A, EulerRot, Quat, Vec3};
+use once_cell::sync::Lazy;
+use strum::EnumCount;
+
+use crate::core::{AppState, INSTRUCTIONS_END, INSTRUCTIONS_START, TRACK_ON};
+
+use super::{
+    bind_udp, is_timeout,
+    packet::PacketReader,
+    region::{Region, RegionTimes},
+    unified::{
+        UnifiedExpressions, UnifiedShapeAccessors, UnifiedShapes, UnifiedTrackingData, NUM_SHAPES,
+    },
+    FaceReceiver, LoopGuard, LoopHandle,
+};
+
+static STA_ON: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "OSF".color(Color::Green)).into());
+static STA_OFF: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "OSF".color(Color::Red)).into());
+
+const NUM_LANDMARKS: usize = 68;
+const NUM_POINTS_3D: usize = 70;
+const NUM_FEATURES: usize = 14;
+
+/// Timestamp, id, camera resolution, eye openness, success, PnP error,
+/// quaternion, euler angles and translation
+const HEADER_LEN: usize = 8 + 4 + 2 * 4 + 2 * 4 + 1 + 4 + 4 * 4 + 3 * 4 + 3 * 4;
+
+/// Size of one face in an OpenSeeFace packet. A packet holds one or more faces back to back.
+const PACKET_LEN: usize =
+    HEADER_LEN + NUM_LANDMARKS * 3 * 4 + NUM_POINTS_3D * 3 * 4 + NUM_FEATURES * 4;
+
+/// What OpenSeeFace's `facetracker.py` sends for each face, all little-endian.
+/// Landmarks and 3D points are skipped, only the derived features are kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct OsfFace {
+    pub id: i32,
+    /// Whether the tracker has a face at all
+    pub success: bool,
+    /// Eye openness, about 1 when open
+    pub eye_open_left: f32,
+    pub eye_open_right: f32,
+    /// Head rotation in degrees, as seen by the camera
+    pub euler: Vec3,
+    pub features: OsfFeatures,
+}
+
+/// The features OpenSeeFace derives from the landmarks, roughly -1..1.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct OsfFeatures {
+    pub eye_l: f32,
+    pub eye_r: f32,
+    pub eyebrow_steepness_l: f32,
+    pub eyebrow_updown_l: f32,
+    pub eyebrow_quirk_l: f32,
+    pub eyebrow_steepness_r: f32,
+    pub eyebrow_updown_r: f32,
+    pub eyebrow_quirk_r: f32,
+    pub mouth_corner_updown_l: f32,
+    pub mouth_corner_inout_l: f32,
+    pub mouth_corner_updown_r: f32,
+    pub mouth_corner_inout_r: f32,
+    pub mouth_open: f32,
+    pub mouth_wide: f32,
+}
+
+pub(super) fn decode(buf: &[u8]) -> anyhow::Result<Vec<OsfFace>> {
+    if buf.is_empty() || buf.len() % PACKET_LEN != 0 {
+        bail!("Bad OpenSeeFace packet length {}", buf.len());
+    }
+    buf.chunks(PACKET_LEN).map(decode_face).collect()
+}
+
+fn decode_face(buf: &[u8]) -> anyhow::Result<OsfFace> {
+    let mut r = PacketReader::new(buf);
+
+    r.skip(8)?; // timestamp
+    let id = i32::from_le_bytes(r.take()?);
+    r.skip(2 * 4)?; // camera resolution
+    let eye_open_right = r.f32_le()?;
+    let eye_open_left = r.f32_le()?;
+    let [success] = r.take()?;
+    r.skip(4 + 4 * 4)?; // PnP error, quaternion
+    let euler = Vec3::new(r.f32_le()?, r.f32_le()?, r.f32_le()?);
+    r.skip(3 * 4)?; // translation
+    r.skip(NUM_LANDMARKS * 3 * 4 + NUM_POINTS_3D * 3 * 4)?; // landmarks, 3D points
+
+    let mut f = || r.f32_le();
+    let features = OsfFeatures {
+        eye_l: f()?,
+        eye_r: f()?,
+        eyebrow_steepness_l: f()?,
+        eyebrow_updown_l: f()?,
+        eyebrow_quirk_l: f()?,
+        eyebrow_steepness_r: f()?,
+        eyebrow_updown_r: f()?,
+        eyebrow_quirk_r: f()?,
+        mouth_corner_updown_l: f()?,
+        mouth_corner_inout_l: f()?,
+        mouth_corner_updown_r: f()?,
+        mouth_corner_inout_r: f()?,
+        mouth_open: f()?,
+        mouth_wide: f()?,
+    };
+
+    Ok(OsfFace {
+        id,
+        success: success != 0,
+        eye_open_left,
+        eye_open_right,
+        euler,
+        features,
+    })
+}
+
+/// Webcams only give a handful of features, so this is a rough approximation.
+pub(super) fn osf_to_unified(face: &OsfFace) -> UnifiedShapes {
+    use UnifiedExpressions as U;
+
+    let mut shapes: UnifiedShapes = [0.0; NUM_SHAPES];
+    let f = &face.features;
+    let pos = |v: f32| v.clamp(0., 1.);
+    let neg = |v: f32| (-v).clamp(0., 1.);
+
+    shapes.setu(U::EyeClosedLeft, 1. - pos(face.eye_open_left));
+    shapes.setu(U::EyeClosedRight, 1. - pos(face.eye_open_right));
+
+    for (left, right, value_l, value_r) in [
+        (
+            U::BrowInnerUpLeft,
+            U::BrowInnerUpRight,
+            pos(f.eyebrow_updown_l),
+            pos(f.eyebrow_updown_r),
+        ),
+        (
+            U::BrowOuterUpLeft,
+            U::BrowOuterUpRight,
+            pos(f.eyebrow_updown_l),
+            pos(f.eyebrow_updown_r),
+        ),
+        (
+            U::BrowLowererLeft,
+            U::BrowLowererRight,
+            neg(f.eyebrow_updown_l),
+            neg(f.eyebrow_updown_r),
+        ),
+        (
+            U::MouthCornerPullLeft,
+            U::MouthCornerPullRight,
+            pos(f.mouth_corner_updown_l),
+            pos(f.mouth_corner_updown_r),
+        ),
+        (
+            U::MouthCornerSlantLeft,
+            U::MouthCornerSlantRight,
+            pos(f.mouth_corner_updown_l),
+            pos(f.mouth_corner_updown_r),
+        ),
+        (
+            U::MouthFrownLeft,
+            U::MouthFrownRight,
+            neg(f.mouth_corner_updown_l),
+            neg(f.mouth_corner_updown_r),
+        ),
+        (
+            U::MouthStretchLeft,
+            U::MouthStretchRight,
+            pos(f.mouth_wide),
+            pos(f.mouth_wide),
+        ),
+    ] {
+        shapes.setu(left, value_l);
+        shapes.setu(right, value_r);
+    }
+
+    shapes.setu(U::JawOpen, pos(f.mouth_open));
+
+    shapes
+}
+
+/// OpenSeeFace reports about ±180° pitch when facing the camera, and yaw as the camera sees it.
+fn head_rotation(euler: Vec3) -> Quat {
+    let wrap = |deg: f32| (deg + 180.).rem_euclid(360.) - 180.;
+    Quat::from_euler(
+        EulerRot::YXZ,
+        -euler.y.to_radians(),
+        wrap(euler.x + 180.).to_radians(),
+        -euler.z.to_radians(),
+    )
+}
+
+pub(super) struct OpenSeeFaceReceiver {
+    listen_port: u16,
+    sender: SyncSender<OsfFace>,
+    receiver: Receiver<OsfFace>,
+    last_received: Option<Instant>,
+    guard: LoopGuard,
+}
+
+impl OpenSeeFaceReceiver {
+    pub fn new(listen_port: u16) -> Self {
+        let (sender, receiver) = sync_channel(8);
+        Self {
+            listen_port,
+            sender,
+            receiver,
+            last_received: None,
+            guard: LoopGuard::default(),
+        }
+    }
+}
+
+impl FaceReceiver for OpenSeeFaceReceiver {
+    fn start_loop(&mut self) {
+        log::info!("{}", *INSTRUCTIONS_START);
+        log::info!("");
+        log::info!("Selected OpenSeeFace (webcam) to provide face data.");
+        log::info!("");
+        log::info!(
+            "• Start: {}",
+            format!("python facetracker.py -c 0 --port {}", self.listen_port)
+                .on_color(Color::White)
+                .color(Color::Black)
+        );
+        log::info!("");
+        log::info!("Status bar tickers:");
+        log::info!("• {} → a face is being tracked", *STA_ON);
+        log::info!("• {} → head data is being received", *TRACK_ON);
+        log::info!("");
+        log::info!("{}", *INSTRUCTIONS_END);
+
+        let listen_port = self.listen_port;
+        let sender = self.sender.clone();
+        let handle = self.guard.handle();
+        thread::spawn(move || osf_loop(listen_port, sender, handle));
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        if let Some(face) = self.receiver.try_iter().last() {
+            let shapes = osf_to_unified(&face);
+            data.shapes[..UnifiedExpressions::COUNT]
+                .copy_from_slice(&shapes[..UnifiedExpressions::COUNT]);
+
+            let (_, _, translation) = state.tracking.head.to_scale_rotation_translation();
+            state.tracking.head =
+                Affine3A::from_rotation_translation(head_rotation(face.euler), translation);
+            state.tracking.last_received = Instant::now();
+            self.last_received = Some(Instant::now());
+        }
+
+        if self
+            .last_received
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
+            state.status.add_item(STA_ON.clone());
+        } else {
+            state.status.add_item(STA_OFF.clone());
+        }
+    }
+
+    fn last_update(&self) -> RegionTimes {
+        [self.last_received; Region::COUNT]
+    }
+}
+
+fn osf_loop(listen_port: u16, sender: SyncSender<OsfFace>, handle: LoopHandle) {
+    let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), listen_port);
+    let Some(listener) = bind_udp("OpenSeeFace", addr) else {
+        return;
+    };
+
+    let mut buf = [0u8; 8192];
+    while !handle.is_stopped() {
+        let size = match listener.recv(&mut buf) {
+            Ok(size) => size,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                log::warn!("OpenSeeFace: failed to receive a packet: {}", e);
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let faces = match decode(&buf[..size]) {
+            Ok(faces) => faces,
+            Err(e) => {
+                log::debug!("OpenSeeFace: {e:#}");
+                continue;
+            }
+        };
+        // with --faces above 1, follow whichever face has the lowest id
+        if let Some(face) = faces
+            .into_iter()
+            .filter(|face| face.success)
+            .min_by_key(|face| face.id)
+        {
+            let _ = sender.try_send(face);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f32s(buf: &mut Vec<u8>, values: &[f32]) {
+        for value in values.iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn packet(face: &OsfFace) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(&12.5f64.to_le_bytes());
+        buf.extend_from_slice(&face.id.to_le_bytes());
+        f32s(
+            &mut buf,
+            &[640., 480., face.eye_open_right, face.eye_open_left],
+        );
+        buf.push(face.success as u8);
+        f32s(&mut buf, &[0.1, 0., 0., 0., 1.]);
+        f32s(&mut buf, &face.euler.to_array());
+        f32s(&mut buf, &[0., 0., 0.5]);
+        f32s(&mut buf, &[0.5; NUM_LANDMARKS * 3 + NUM_POINTS_3D * 3]);
+        let f = &face.features;
+        f32s(
+            &mut buf,
+            &[
+                f.eye_l,
+                f.eye_r,
+                f.eyebrow_steepness_l,
+                f.eyebrow_updown_l,
+                f.eyebrow_quirk_l,
+                f.eyebrow_steepness_r,
+                f.eyebrow_updown_r,
+                f.eyebrow_quirk_r,
+                f.mouth_corner_updown_l,
+                f.mouth_corner_inout_l,
+                f.mouth_corner_updown_r,
+                f.mouth_corner_inout_r,
+                f.mouth_open,
+                f.mouth_wide,
+            ],
+        );
+        buf
+    }
+
+    fn smiling_face() -> OsfFace {
+        OsfFace {
+            id: 0,
+            success: true,
+            eye_open_left: 0.2,
+            eye_open_right: 1.1,
+            euler: Vec3::new(-178., 10., 0.),
+            features: OsfFeatures {
+                eyebrow_updown_l: -0.5,
+                mouth_corner_updown_l: 0.6,
+                mouth_corner_updown_r: 0.4,
+                mouth_open: 0.3,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_packets() {
+        let face = smiling_face();
+        let buf = packet(&face);
+        assert_eq!(buf.len(), PACKET_LEN);
+        assert_eq!(PACKET_LEN, 1785);
+        assert_eq!(decode(&buf).unwrap(), vec![face]);
+
+        let mut two = buf.clone();
+        two.extend_from_slice(&buf);
+        assert_eq!(decode(&two).unwrap().len(), 2);
+
+        assert!(decode(&buf[..100]).is_err());
+    }
+
+    #[test]
+    fn derives_expressions() {
+        let shapes = osf_to_unified(&smiling_face());
+        let get = |e| shapes.getu(e);
+
+        assert!((get(UnifiedExpressions::EyeClosedLeft) - 0.8).abs() < 1e-6);
+        assert_eq!(get(UnifiedExpressions::EyeClosedRight), 0.);
+        assert_eq!(get(UnifiedExpressions::BrowLowererLeft), 0.5);
+        assert_eq!(get(UnifiedExpressions::BrowInnerUpLeft), 0.);
+        assert_eq!(get(UnifiedExpressions::MouthCornerPullLeft), 0.6);
+        assert_eq!(get(UnifiedExpressions::MouthCornerPullRight), 0.4);
+        assert_eq!(get(UnifiedExpressions::JawOpen), 0.3);
+    }
+
+    #[test]
+    fn head_faces_forward_at_180_pitch() {
+        let rotation = head_rotation(Vec3::new(180., 0., 0.));
+        assert!(rotation.abs_diff_eq(Quat::IDENTITY, 1e-6));
+        let rotation = head_rotation(Vec3::new(-170., 0., 0.));
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_x(10f32.to_radians()), 1e-6));
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::bail;
+
+/// Reads fixed-size fields off the front of a binary UDP packet.
+pub(super) struct PacketReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            bail!("Packet too short");
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn f32_le(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn skip(&mut self, len: usize) -> anyhow::Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    f32::consts::{PI, TAU},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::core::{AppState, INSTRUCTIONS_END, INSTRUCTIONS_START, TRACK_ON};
+
+use super::{
+    arkit::EYE_RANGE_RAD,
+    unified::{UnifiedExpressions, UnifiedTrackingData},
+    FaceReceiver,
+};
+
+const BLINK_CLOSE: Duration = Duration::from_millis(70);
+const BLINK_OPEN: Duration = Duration::from_millis(130);
+/// Seconds between blinks
+const BLINK_INTERVAL: (f32, f32) = (2., 6.);
+/// Seconds between saccades
+const SACCADE_INTERVAL: (f32, f32) = (0.4, 2.5);
+/// Largest glance away from straight ahead, in radians
+const SACCADE_PITCH: f32 = 0.08;
+const SACCADE_YAW: f32 = 0.15;
+/// Seconds between the tiny fixation corrections
+const MICRO_SACCADE_INTERVAL: (f32, f32) = (0.15, 0.6);
+const MICRO_SACCADE: f32 = 0.01;
+/// How far ahead of a head turn the eyes look, in seconds of head motion
+const HEAD_LEAD_SECS: f32 = 0.2;
+/// Largest lead, in radians
+const HEAD_LEAD_MAX: f32 = 0.3;
+/// Time constant for the lead to settle, since head poses arrive unevenly
+const HEAD_LEAD_SMOOTHING_SECS: f32 = 0.08;
+
+/// Blinks and small glances around, for when there is no eye tracking.
+pub(super) struct ProceduralEyes {
+    rng: u32,
+    next_blink: Instant,
+    blink_start: Option<Instant>,
+    next_saccade: Instant,
+    next_micro_saccade: Instant,
+    /// Pitch and yaw, in radians
+    gaze: (f32, f32),
+    micro_saccade: (f32, f32),
+    /// Where the eyes glance ahead of the head turning
+    lead: (f32, f32),
+    last_head: Option<(f32, f32)>,
+}
+
+impl ProceduralEyes {
+    pub fn new(now: Instant) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.subsec_nanos());
+        Self::with_seed(seed, now)
+    }
+
+    fn with_seed(seed: u32, now: Instant) -> Self {
+        let mut me = Self {
+            rng: seed | 1,
+            next_blink: now,
+            blink_start: None,
+            next_saccade: now,
+            next_micro_saccade: now,
+            gaze: (0., 0.),
+            micro_saccade: (0., 0.),
+            lead: (0., 0.),
+            last_head: None,
+        };
+        me.next_blink = now + me.interval(BLINK_INTERVAL);
+        me
+    }
+
+    /// xorshift32, which is plenty for eye movements
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn interval(&mut self, (min, max): (f32, f32)) -> Duration {
+        Duration::from_secs_f32(min + (max - min) * self.random())
+    }
+
+    /// How far the lids are closed at `now`, from 0 to 1.
+    fn blink(&mut self, now: Instant) -> f32 {
+        if self.blink_start.is_none() && now >= self.next_blink {
+            self.blink_start = Some(now);
+        }
+        let Some(start) = self.blink_start else {
+            return 0.;
+        };
+
+        let t = now.saturating_duration_since(start);
+        if t < BLINK_CLOSE {
+            t.as_secs_f32() / BLINK_CLOSE.as_secs_f32()
+        } else if t < BLINK_CLOSE + BLINK_OPEN {
+            1. - (t - BLINK_CLOSE).as_secs_f32() / BLINK_OPEN.as_secs_f32()
+        } else {
+            self.blink_start = None;
+            self.next_blink = now + self.interval(BLINK_INTERVAL);
+            0.
+        }
+    }
+
+    fn saccade(&mut self, now: Instant) -> (f32, f32) {
+        if now >= self.next_saccade {
+            // saccades are fast enough to be a jump at any frame rate
+            self.gaze = (
+                (self.random() * 2. - 1.) * SACCADE_PITCH,
+                (self.random() * 2. - 1.) * SACCADE_YAW,
+            );
+            self.next_saccade = now + self.interval(SACCADE_INTERVAL);
+        }
+        if now >= self.next_micro_saccade {
+            self.micro_saccade = (
+                (self.random() * 2. - 1.) * MICRO_SACCADE,
+                (self.random() * 2. - 1.) * MICRO_SACCADE,
+            );
+            self.next_micro_saccade = now + self.interval(MICRO_SACCADE_INTERVAL);
+        }
+        (
+            self.gaze.0 + self.micro_saccade.0,
+            self.gaze.1 + self.micro_saccade.1,
+        )
+    }
+
+    /// Makes the eyes lead head turns, the way people look where they are turning to.
+    /// Pass `None` while there is no head tracking.
+    pub fn follow_head(&mut self, head: Option<Quat>, dt: f32) {
+        let angles = head.map(|head| {
+            let (yaw, pitch, _) = head.to_euler(EulerRot::YXZ);
+            (pitch, yaw)
+        });
+        let target = match (angles, self.last_head) {
+            (Some(angles), Some(last)) if dt > 0. => {
+                let lead = |angle: f32, last: f32| {
+                    let delta = (angle - last + PI).rem_euclid(TAU) - PI;
+                    (delta / dt * HEAD_LEAD_SECS).clamp(-HEAD_LEAD_MAX, HEAD_LEAD_MAX)
+                };
+                (lead(angles.0, last.0), lead(angles.1, last.1))
+            }
+            _ => (0., 0.),
+        };
+        self.last_head = angles;
+
+        let smoothing = 1. - (-dt / HEAD_LEAD_SMOOTHING_SECS).exp();
+        self.lead.0 += (target.0 - self.lead.0) * smoothing;
+        self.lead.1 += (target.1 - self.lead.1) * smoothing;
+    }
+
+    /// Writes the eye region of `data` as it should look at `now`.
+    pub fn write(&mut self, now: Instant, data: &mut UnifiedTrackingData) {
+        use UnifiedExpressions as U;
+
+        let closed = self.blink(now);
+        let (pitch, yaw) = self.saccade(now);
+        let pitch = (pitch + self.lead.0).clamp(-EYE_RANGE_RAD, EYE_RANGE_RAD);
+        let yaw = (yaw + self.lead.1).clamp(-EYE_RANGE_RAD, EYE_RANGE_RAD);
+
+        data.setu(U::EyeClosedLeft, closed);
+        data.setu(U::EyeClosedRight, closed);
+        for shape in [
+            U::EyeSquintLeft,
+            U::EyeSquintRight,
+            U::EyeWideLeft,
+            U::EyeWideRight,
+        ] {
+            data.setu(shape, 0.);
+        }
+        // unified X looks right, while a positive yaw looks left
+        data.setu(U::EyeLeftX, -yaw / EYE_RANGE_RAD);
+        data.setu(U::EyeRightX, -yaw / EYE_RANGE_RAD);
+        data.setu(U::EyeY, pitch / EYE_RANGE_RAD);
+        data.eyes = [Some(Vec3::new(pitch, yaw, 0.)); 2];
+    }
+}
+
+/// Drives the eyes without an eye tracker, for `FaceSetup::Procedural`.
+pub(super) struct ProceduralReceiver {
+    eyes: ProceduralEyes,
+}
+
+impl ProceduralReceiver {
+    pub fn new() -> Self {
+        Self {
+            eyes: ProceduralEyes::new(Instant::now()),
+        }
+    }
+}
+
+impl FaceReceiver for ProceduralReceiver {
+    fn start_loop(&mut self) {
+        log::info!("{}", *INSTRUCTIONS_START);
+        log::info!("");
+        log::info!("Selected procedural eyes: blinking and glancing around without eye tracking.");
+        log::info!("");
+        log::info!(
+            "• The eyes look ahead of head turns while {} is shown",
+            *TRACK_ON
+        );
+        log::info!("• To use a mouth tracker as well, combine both with the composite provider");
+        log::info!("");
+        log::info!("{}", *INSTRUCTIONS_END);
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        let head = (state.tracking.last_received.elapsed() < Duration::from_secs(1))
+            .then(|| state.tracking.head.to_scale_rotation_translation().1);
+        self.eyes.follow_head(head, state.delta_t);
+        self.eyes.write(Instant::now(), data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blinks_and_glances_around() {
+        let start = Instant::now();
+        let mut eyes = ProceduralEyes::with_seed(42, start);
+        let mut data = UnifiedTrackingData::default();
+
+        let mut blinks = 0;
+        let mut was_closed = false;
+        let mut saccades = 0;
+        let mut last_gaze = eyes.gaze;
+        // a minute at 90 frames per second
+        for frame in 0..90 * 60 {
+            let now = start + Duration::from_secs_f32(frame as f32 / 90.);
+            eyes.write(now, &mut data);
+
+            let closed = data.getu(UnifiedExpressions::EyeClosedLeft);
+            assert!((0. ..=1.).contains(&closed));
+            if closed > 0.9 && !was_closed {
+                blinks += 1;
+            }
+            was_closed = closed > 0.9;
+
+            let gaze = data.eyes[0].unwrap();
+            assert!(gaze.x.abs() <= SACCADE_PITCH + MICRO_SACCADE);
+            assert!(gaze.y.abs() <= SACCADE_YAW + MICRO_SACCADE);
+            if eyes.gaze != last_gaze {
+                saccades += 1;
+                last_gaze = eyes.gaze;
+            }
+        }
+
+        // one blink every 2 to 6 seconds, one saccade every 0.4 to 2.5 seconds
+        assert!((10..=30).contains(&blinks), "{} blinks", blinks);
+        assert!((24..=150).contains(&saccades), "{} saccades", saccades);
+    }
+
+    #[test]
+    fn looks_ahead_of_head_turns() {
+        let start = Instant::now();
+        let mut eyes = ProceduralEyes::with_seed(7, start);
+        let mut data = UnifiedTrackingData::default();
+        let dt = 1. / 90.;
+
+        // turn the head left at 90° per second for a third of a second
+        for frame in 0..30 {
+            let yaw = frame as f32 * dt * PI / 2.;
+            eyes.follow_head(Some(Quat::from_rotation_y(yaw)), dt);
+        }
+        assert!(eyes.lead.1 > 0.2, "lead {}", eyes.lead.1);
+        eyes.write(start, &mut data);
+        assert!(data.eyes[0].unwrap().y > 0.);
+        assert!(data.getu(UnifiedExpressions::EyeLeftX) < 0.);
+
+        // and settle once it stops
+        for _ in 0..90 {
+            eyes.follow_head(Some(Quat::from_rotation_y(PI / 6.)), dt);
+        }
+        eyes.write(start, &mut data);
+        assert!(data.eyes[0].unwrap().y.abs() <= SACCADE_YAW + MICRO_SACCADE);
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use colored::{Color, Colorize};
+use glam::{Affine3A, Quat, Vec3};
+use once_cell::sync::Lazy;
+use strum::EnumCount;
+
+use crate::core::{AppState, OscTrack};
+
+use super::{
+    region::{Region, RegionTimes},
+    unified::{UnifiedShapes, UnifiedTrackingData, NUM_SHAPES},
+    FaceReceiver,
+};
+
+static STA_REPLAY: Lazy<Arc<str>> =
+    Lazy::new(|| format!("{}", "REPLAY".color(Color::Green)).into());
+static STA_REPLAY_END: Lazy<Arc<str>> =
+    Lazy::new(|| format!("{}", "REPLAY".color(Color::BrightBlack)).into());
+
+const MAGIC: &[u8; 4] = b"OAMR";
+const VERSION: u16 = 1;
+
+const FLAG_LEFT_EYE: u8 = 1;
+const FLAG_RIGHT_EYE: u8 = 2;
+const FLAG_TRACKING: u8 = 4;
+
+/// One tick of a recorded session.
+///
+/// File layout, little-endian: `OAMR`, version u16, shape count u16, then per frame:
+/// time since start in µs (u64), flags (u8), shapes (f32 each), both eyes (3×f32 each),
+/// and head, left and right hand poses (rotation 4×f32, then position 3×f32).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub time: Duration,
+    pub shapes: UnifiedShapes,
+    pub eyes: [Option<Vec3>; 2],
+    pub poses: [Affine3A; 3],
+    /// Whether head tracking was being received when the frame was recorded
+    pub tracking: bool,
+}
+
+impl Frame {
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut flags = 0u8;
+        if self.eyes[0].is_some() {
+            flags |= FLAG_LEFT_EYE;
+        }
+        if self.eyes[1].is_some() {
+            flags |= FLAG_RIGHT_EYE;
+        }
+        if self.tracking {
+            flags |= FLAG_TRACKING;
+        }
+
+        w.write_all(&(self.time.as_micros() as u64).to_le_bytes())?;
+        w.write_all(&[flags])?;
+        write_f32s(w, &self.shapes)?;
+        for eye in self.eyes.iter() {
+            write_f32s(w, &eye.unwrap_or(Vec3::ZERO).to_array())?;
+        }
+        for pose in self.poses.iter() {
+            let (_, rotation, translation) = pose.to_scale_rotation_translation();
+            write_f32s(w, &rotation.to_array())?;
+            write_f32s(w, &translation.to_array())?;
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read, num_shapes: usize) -> io::Result<Self> {
+        let mut time = [0u8; 8];
+        r.read_exact(&mut time)?;
+        let mut flags = [0u8; 1];
+        r.read_exact(&mut flags)?;
+        let flags = flags[0];
+
+        // files from other versions may have more or fewer shapes
+        let mut shapes = [0f32; NUM_SHAPES];
+        for i in 0..num_shapes {
+            let value = read_f32(r)?;
+            if let Some(shape) = shapes.get_mut(i) {
+                *shape = value;
+            }
+        }
+
+        let mut eyes = [None; 2];
+        for (i, eye) in eyes.iter_mut().enumerate() {
+            let value = Vec3::from_array(read_f32s(r)?);
+            if flags & [FLAG_LEFT_EYE, FLAG_RIGHT_EYE][i] != 0 {
+                *eye = Some(value);
+            }
+        }
+
+        let mut poses = [Affine3A::IDENTITY; 3];
+        for pose in poses.iter_mut() {
+            let rotation = Quat::from_array(read_f32s(r)?);
+            let translation = Vec3::from_array(read_f32s(r)?);
+            *pose = Affine3A::from_rotation_translation(rotation, translation);
+        }
+
+        Ok(Self {
+            time: Duration::from_micros(u64::from_le_bytes(time)),
+            shapes,
+            eyes,
+            poses,
+            tracking: flags & FLAG_TRACKING != 0,
+        })
+    }
+}
+
+fn write_f32s(w: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    for value in values.iter() {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_f32(r: &mut impl Read) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+fn read_f32s<const N: usize>(r: &mut impl Read) -> io::Result<[f32; N]> {
+    let mut values = [0f32; N];
+    for value in values.iter_mut() {
+        *value = read_f32(r)?;
+    }
+    Ok(values)
+}
+
+fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(NUM_SHAPES as u16).to_le_bytes())
+}
+
+/// Reads a whole recording. A truncated last frame (e.g. oscavmgr was killed) is dropped.
+pub fn read_frames(r: &mut impl Read) -> anyhow::Result<Vec<Frame>> {
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)
+        .context("File too short for a recording")?;
+    if &header[0..4] != MAGIC {
+        bail!("Not an oscavmgr recording");
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        bail!("Unsupported recording version {}", version);
+    }
+    let num_shapes = u16::from_le_bytes([header[6], header[7]]) as usize;
+
+    let mut frames = Vec::new();
+    loop {
+        match Frame::read(r, num_shapes) {
+            Ok(frame) => frames.push(frame),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(frames)
+}
+
+/// Writes what oscavmgr saw each tick, for replaying with the `replay` provider.
+pub struct Recorder {
+    path: String,
+    writer: BufWriter<File>,
+    start: Instant,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("Could not create {}", path))?;
+        let mut writer = BufWriter::new(file);
+        write_header(&mut writer)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            writer,
+            start: Instant::now(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, data: &UnifiedTrackingData, tracking: &OscTrack) -> io::Result<()> {
+        let frame = Frame {
+            time: self.start.elapsed(),
+            shapes: data.shapes,
+            eyes: data.eyes,
+            poses: [tracking.head, tracking.left_hand, tracking.right_hand],
+            tracking: tracking.last_received.elapsed() < Duration::from_secs(1),
+        };
+        frame.write(&mut self.writer)?;
+
+        // keep the file usable if we get killed
+        if self.last_flush.elapsed() > Duration::from_secs(1) {
+            self.last_flush = Instant::now();
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+pub(super) struct ReplayReceiver {
+    path: String,
+    speed: f32,
+    looping: bool,
+    frames: Vec<Frame>,
+    next: usize,
+    start: Instant,
+    /// When the last frame was applied
+    last_frame: Option<Instant>,
+}
+
+impl ReplayReceiver {
+    pub fn new(path: &str, speed: f32, looping: bool) -> Self {
+        Self {
+            path: path.to_string(),
+            speed,
+            looping,
+            frames: Vec::new(),
+            next: 0,
+            start: Instant::now(),
+            last_frame: None,
+        }
+    }
+}
+
+impl FaceReceiver for ReplayReceiver {
+    fn start_loop(&mut self) {
+        let frames = File::open(&self.path)
+            .with_context(|| format!("Could not open {}", self.path))
+            .and_then(|file| read_frames(&mut BufReader::new(file)));
+
+        match frames {
+            Ok(frames) => {
+                log::info!(
+                    "Replaying {} frames ({:.1}s) from {} at {}x speed",
+                    frames.len(),
+                    frames.last().map(|f| f.time.as_secs_f32()).unwrap_or(0.),
+                    self.path,
+                    self.speed
+                );
+                self.frames = frames;
+            }
+            Err(e) => log::error!("Replay: {e:#}"),
+        }
+        self.start = Instant::now();
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        if self.frames.is_empty() {
+            return;
+        }
+
+        if self.next >= self.frames.len() {
+            if !self.looping {
+                state.status.add_item(STA_REPLAY_END.clone());
+                return;
+            }
+            self.next = 0;
+            self.start = Instant::now();
+        }
+
+        let elapsed = self.start.elapsed().mul_f32(self.speed);
+        let mut current = None;
+        while self.next < self.frames.len() && self.frames[self.next].time <= elapsed {
+            current = Some(self.next);
+            self.next += 1;
+        }
+        if self.next >= self.frames.len() && !self.looping {
+            log::info!("Replay finished.");
+        }
+
+        state.status.add_item(STA_REPLAY.clone());
+
+        let Some(current) = current else {
+            return;
+        };
+        let frame = &self.frames[current];
+        self.last_frame = Some(Instant::now());
+
+        data.shapes = frame.shapes;
+        data.eyes = frame.eyes;
+
+        let [head, left_hand, right_hand] = frame.poses;
+        state.tracking.head = head;
+        state.tracking.left_hand = left_hand;
+        state.tracking.right_hand = right_hand;
+        if frame.tracking {
+            state.tracking.last_received = Instant::now();
+        }
+    }
+
+    fn last_update(&self) -> RegionTimes {
+        [self.last_frame; Region::COUNT]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_roundtrip() {
+        let mut shapes = [0f32; NUM_SHAPES];
+        shapes[0] = 0.25;
+        shapes[NUM_SHAPES - 1] = -1.;
+
+        let frames = vec![
+            Frame {
+                time: Duration::from_micros(0),
+                shapes,
+                eyes: [Some(Vec3::new(0.1, -0.2, 0.)), None],
+                poses: [
+                    Affine3A::from_rotation_translation(
+                        Quat::from_rotation_y(1.),
+                        Vec3::new(0., 1.7, 0.),
+                    ),
+                    Affine3A::IDENTITY,
+                    Affine3A::from_translation(Vec3::X),
+                ],
+                tracking: true,
+            },
+            Frame {
+                time: Duration::from_micros(11_111),
+                shapes: [0.5; NUM_SHAPES],
+                eyes: [None, Some(Vec3::Z)],
+                poses: [Affine3A::IDENTITY; 3],
+                tracking: false,
+            },
+        ];
+
+        let mut buf = Vec::new();
+        write_header(&mut buf).unwrap();
+        for frame in frames.iter() {
+            frame.write(&mut buf).unwrap();
+        }
+        // a half-written frame at the end is ignored
+        buf.extend_from_slice(&[0u8; 10]);
+
+        let read = read_frames(&mut buf.as_slice()).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].shapes, frames[0].shapes);
+        assert_eq!(read[0].eyes, frames[0].eyes);
+        assert!(read[0].poses[0].abs_diff_eq(frames[0].poses[0], 1e-6));
+        assert!(read[0].tracking);
+        assert_eq!(read[1].time, frames[1].time);
+        assert_eq!(read[1].eyes, frames[1].eyes);
+        assert!(!read[1].tracking);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read_frames(&mut &b"{\"json\": true}"[..]).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use strum::{EnumCount, EnumIter, IntoEnumIterator, IntoStaticStr};
+
+use super::unified::{UnifiedExpressions, UnifiedTrackingData};
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumIter, EnumCount, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub(super) enum Region {
+    Eyes,
+    Brows,
+    Mouth,
+    Tongue,
+}
+
+impl Region {
+    pub fn range(self) -> RangeInclusive<usize> {
+        use UnifiedExpressions as U;
+        match self {
+            Region::Eyes => U::EyeLeftX as usize..=U::EyeWideLeft as usize,
+            Region::Brows => U::BrowPinchRight as usize..=U::BrowOuterUpLeft as usize,
+            Region::Mouth => U::NasalDilationRight as usize..=U::MouthTightenerLeft as usize,
+            Region::Tongue => U::TongueOut as usize..=U::TongueTwistLeft as usize,
+        }
+    }
+}
+
+/// When each region last got data from the device, as reported by a receiver
+pub(super) type RegionTimes = [Option<Instant>; Region::COUNT];
+
+/// A region counts as live while its receiver keeps getting data for it.
+/// Where the receiver can't tell, it counts as live for as long as its values keep moving.
+pub(super) struct Liveness {
+    last_data: UnifiedTrackingData,
+    last_change: RegionTimes,
+    last_update: RegionTimes,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            last_data: UnifiedTrackingData::default(),
+            last_change: [None; Region::COUNT],
+            last_update: [None; Region::COUNT],
+        }
+    }
+}
+
+impl Liveness {
+    pub fn update(&mut self, data: &UnifiedTrackingData, last_update: RegionTimes, now: Instant) {
+        self.last_update = last_update;
+        for region in Region::iter() {
+            let range = region.range();
+            let mut changed = data.shapes[range.clone()] != self.last_data.shapes[range];
+            if let Region::Eyes = region {
+                changed |= data.eyes != self.last_data.eyes;
+            }
+            if changed {
+                self.last_change[region as usize] = Some(now);
+            }
+        }
+        self.last_data.shapes = data.shapes;
+        self.last_data.eyes = data.eyes;
+    }
+
+    pub fn is_live(&self, region: Region, now: Instant, stale_after: Duration) -> bool {
+        self.last_update[region as usize]
+            .or(self.last_change[region as usize])
+            .is_some_and(|t| now.saturating_duration_since(t) < stale_after)
+    }
+}
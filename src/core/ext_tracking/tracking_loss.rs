@@ -0,0 +1,224 @@
+use std::time::{Duration, Instant};
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::core::config::TrackingLossConfig;
+
+use super::{
+    procedural::ProceduralEyes,
+    region::{Liveness, Region, RegionTimes},
+    unified::UnifiedTrackingData,
+};
+
+/// Fades each face region to neutral once the receiver stops getting data for it,
+/// so a dropped tracker doesn't leave the mouth hanging open.
+pub(super) struct TrackingLoss {
+    liveness: Liveness,
+    /// 1 while a region is tracked, 0 once it has faded out
+    weights: [f32; Region::COUNT],
+    procedural: Option<ProceduralEyes>,
+    timeout: Duration,
+    fade: Duration,
+    any_live: bool,
+}
+
+impl TrackingLoss {
+    pub fn new(config: &TrackingLossConfig) -> Self {
+        Self {
+            liveness: Liveness::default(),
+            weights: [0.; Region::COUNT],
+            procedural: config
+                .procedural_eyes
+                .then(|| ProceduralEyes::new(Instant::now())),
+            timeout: Duration::from_millis(config.timeout_ms),
+            fade: Duration::from_millis(config.fade_ms),
+            any_live: false,
+        }
+    }
+
+    /// Keeps the fade state, so that changing the settings doesn't snap the face.
+    pub fn reconfigure(&mut self, config: &TrackingLossConfig) {
+        self.timeout = Duration::from_millis(config.timeout_ms);
+        self.fade = Duration::from_millis(config.fade_ms);
+        if config.procedural_eyes != self.procedural.is_some() {
+            self.procedural = config
+                .procedural_eyes
+                .then(|| ProceduralEyes::new(Instant::now()));
+        }
+    }
+
+    /// Fades the regions of `data` that the receiver hasn't updated within the timeout.
+    /// Where `last_update` is unknown, `raw` is watched for changes instead.
+    pub fn apply(
+        &mut self,
+        raw: &UnifiedTrackingData,
+        last_update: RegionTimes,
+        data: &mut UnifiedTrackingData,
+        now: Instant,
+        dt: f32,
+    ) {
+        self.liveness.update(raw, last_update, now);
+
+        let mut neutral = UnifiedTrackingData::default();
+        if let Some(procedural) = self.procedural.as_mut() {
+            procedural.write(now, &mut neutral);
+        }
+
+        let step = if self.fade.is_zero() {
+            1.
+        } else {
+            dt / self.fade.as_secs_f32()
+        };
+
+        let mut any_live = false;
+        for region in Region::iter() {
+            let live = self.liveness.is_live(region, now, self.timeout);
+            any_live |= live;
+
+            let weight = &mut self.weights[region as usize];
+            *weight = if live {
+                (*weight + step).min(1.)
+            } else {
+                (*weight - step).max(0.)
+            };
+            let weight = *weight;
+            if weight == 1. {
+                continue;
+            }
+
+            for i in region.range() {
+                data.shapes[i] = neutral.shapes[i] + (data.shapes[i] - neutral.shapes[i]) * weight;
+            }
+            if let Region::Eyes = region {
+                for (eye, neutral) in data.eyes.iter_mut().zip(neutral.eyes) {
+                    *eye = match (*eye, neutral) {
+                        (Some(eye), Some(neutral)) => Some(neutral + (eye - neutral) * weight),
+                        // without procedural eyes, VRChat's own eye animation takes over
+                        (Some(eye), None) if weight > 0. => Some(eye * weight),
+                        (_, neutral) => neutral,
+                    };
+                }
+            }
+        }
+
+        if any_live != self.any_live {
+            if any_live {
+                log::info!("Face data resumed");
+            } else {
+                log::info!(
+                    "No face data for {} ms, fading to neutral",
+                    self.timeout.as_millis()
+                );
+            }
+            self.any_live = any_live;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ext_tracking::unified::UnifiedExpressions;
+
+    fn tracking_loss(procedural_eyes: bool) -> TrackingLoss {
+        TrackingLoss::new(&TrackingLossConfig {
+            timeout_ms: 500,
+            fade_ms: 200,
+            procedural_eyes,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn fades_a_frozen_region_to_neutral() {
+        let mut loss = tracking_loss(false);
+        let start = Instant::now();
+        let dt = 0.1;
+        let mut raw = UnifiedTrackingData::default();
+
+        let frame = |loss: &mut TrackingLoss, raw: &UnifiedTrackingData, secs: f32| {
+            let mut data = raw.clone();
+            let now = start + Duration::from_secs_f32(secs);
+            loss.apply(raw, [None; Region::COUNT], &mut data, now, dt);
+            data
+        };
+
+        // the mouth keeps moving, then freezes wide open
+        for i in 0..5 {
+            raw.setu(UnifiedExpressions::JawOpen, 0.5 + i as f32 * 0.1);
+            raw.setu(UnifiedExpressions::BrowInnerUpLeft, 0.1 * i as f32);
+            frame(&mut loss, &raw, i as f32 * dt);
+        }
+        let data = frame(&mut loss, &raw, 0.5);
+        assert_eq!(
+            data.getu(UnifiedExpressions::JawOpen),
+            raw.getu(UnifiedExpressions::JawOpen)
+        );
+
+        let data = frame(&mut loss, &raw, 1.0);
+        assert!((data.getu(UnifiedExpressions::JawOpen) - 0.45).abs() < 1e-5);
+        let data = frame(&mut loss, &raw, 1.1);
+        assert_eq!(data.getu(UnifiedExpressions::JawOpen), 0.);
+
+        // and fades back in once it moves again
+        raw.setu(UnifiedExpressions::JawOpen, 0.6);
+        let data = frame(&mut loss, &raw, 1.2);
+        assert!((data.getu(UnifiedExpressions::JawOpen) - 0.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn procedural_eyes_replace_missing_eye_tracking() {
+        let mut loss = tracking_loss(true);
+        let raw = UnifiedTrackingData::default();
+        let mut data = raw.clone();
+        loss.apply(
+            &raw,
+            [None; Region::COUNT],
+            &mut data,
+            Instant::now(),
+            0.011,
+        );
+        assert!(data.eyes[0].is_some());
+
+        let mut loss = tracking_loss(false);
+        let mut data = raw.clone();
+        loss.apply(
+            &raw,
+            [None; Region::COUNT],
+            &mut data,
+            Instant::now(),
+            0.011,
+        );
+        assert!(data.eyes[0].is_none());
+    }
+
+    #[test]
+    fn fades_once_the_receiver_goes_silent() {
+        let mut loss = tracking_loss(false);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut raw = UnifiedTrackingData::default();
+        raw.setu(UnifiedExpressions::JawOpen, 0.5);
+
+        let mut frame = |ms: u64, last_packet_ms: u64| {
+            let mut data = raw.clone();
+            let last_update = [Some(at(last_packet_ms)); Region::COUNT];
+            loss.apply(&raw, last_update, &mut data, at(ms), 0.1);
+            data.getu(UnifiedExpressions::JawOpen)
+        };
+
+        // a face held still is not lost while packets keep coming
+        for ms in (0..=1000).step_by(100) {
+            frame(ms, ms);
+        }
+        assert_eq!(frame(1100, 1100), 0.5);
+
+        // the receiver stops getting packets
+        assert_eq!(frame(1500, 1100), 0.5);
+        assert!((frame(1600, 1100) - 0.25).abs() < 1e-5);
+        assert_eq!(frame(1700, 1100), 0.);
+
+        // and comes back
+        assert!((frame(1800, 1800) - 0.25).abs() < 1e-5);
+    }
+}
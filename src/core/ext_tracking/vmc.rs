@@ -0,0 +1,279 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use colored::{Color, Colorize};
+use glam::{Affine3A, EulerRot, Quat, Vec3};
+use once_cell::sync::Lazy;
+use rosc::{OscMessage, OscPacket, OscType};
+use strum::EnumCount;
+
+use crate::core::{
+    tracking_input::unity_quat_to_rotation, AppState, INSTRUCTIONS_END, INSTRUCTIONS_START,
+    TRACK_ON,
+};
+
+use super::{
+    arkit::{arkit_to_unified, ArkitShape, ArkitShapes},
+    bind_udp, is_timeout,
+    region::{Region, RegionTimes},
+    unified::{UnifiedExpressions, UnifiedTrackingData},
+    FaceReceiver, LoopGuard, LoopHandle,
+};
+
+static STA_ON: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "VMC".color(Color::Green)).into());
+static STA_OFF: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "VMC".color(Color::Red)).into());
+
+#[derive(Debug, Clone, PartialEq)]
+enum VmcEvent {
+    /// Sent on `/VMC/Ext/Blend/Apply`, with the latest value of every shape
+    Blendshapes(Box<ArkitShapes>),
+    /// Bone rotations, already converted from Unity
+    Head(Quat),
+    Eye(usize, Quat),
+}
+
+/// Receives ARKit / perfect sync blendshapes and the head pose from a VMC protocol sender.
+pub(super) struct VmcReceiver {
+    listen_port: u16,
+    sender: SyncSender<VmcEvent>,
+    receiver: Receiver<VmcEvent>,
+    last_received: Option<Instant>,
+    guard: LoopGuard,
+}
+
+impl VmcReceiver {
+    pub fn new(listen_port: u16) -> Self {
+        let (sender, receiver) = sync_channel(128);
+        Self {
+            listen_port,
+            sender,
+            receiver,
+            last_received: None,
+            guard: LoopGuard::default(),
+        }
+    }
+}
+
+impl FaceReceiver for VmcReceiver {
+    fn start_loop(&mut self) {
+        log::info!("{}", *INSTRUCTIONS_START);
+        log::info!("");
+        log::info!("Selected VMC protocol to provide face data.");
+        log::info!("");
+        log::info!(
+            "• In your tracker, send VMC to this computer's IP address, port {}",
+            format!("{}", self.listen_port).color(Color::Cyan)
+        );
+        log::info!(
+            "• Use {} blendshape names (ARKit / perfect sync)",
+            "EyeBlinkLeft, JawOpen, ...".color(Color::BrightYellow)
+        );
+        log::info!("");
+        log::info!("Status bar tickers:");
+        log::info!("• {} → face data is being received", *STA_ON);
+        log::info!(
+            "• {} → head & wrist data is being received (for AutoPilot)",
+            *TRACK_ON
+        );
+        log::info!("");
+        log::info!("{}", *INSTRUCTIONS_END);
+
+        let listen_port = self.listen_port;
+        let sender = self.sender.clone();
+        let handle = self.guard.handle();
+        thread::spawn(move || vmc_loop(listen_port, sender, handle));
+    }
+
+    fn receive(&mut self, data: &mut UnifiedTrackingData, state: &mut AppState) {
+        for event in self.receiver.try_iter() {
+            match event {
+                VmcEvent::Blendshapes(arkit) => {
+                    if let Some(shapes) = arkit_to_unified(&arkit[..]) {
+                        data.shapes[..UnifiedExpressions::COUNT]
+                            .copy_from_slice(&shapes[..UnifiedExpressions::COUNT]);
+                        self.last_received = Some(Instant::now());
+                    }
+                }
+                VmcEvent::Head(rotation) => {
+                    // VMC bone positions are relative to the avatar, so only the rotation is used
+                    let (_, _, translation) = state.tracking.head.to_scale_rotation_translation();
+                    state.tracking.head =
+                        Affine3A::from_rotation_translation(rotation, translation);
+                    state.tracking.last_received = Instant::now();
+                }
+                VmcEvent::Eye(eye, rotation) => {
+                    let (y, x, z) = rotation.to_euler(EulerRot::YXZ);
+                    data.eyes[eye] = Some(Vec3 { x, y, z });
+                }
+            }
+        }
+
+        if self
+            .last_received
+            .is_some_and(|t| t.elapsed() < Duration::from_secs(1))
+        {
+            state.status.add_item(STA_ON.clone());
+        } else {
+            state.status.add_item(STA_OFF.clone());
+        }
+    }
+
+    fn last_update(&self) -> RegionTimes {
+        [self.last_received; Region::COUNT]
+    }
+}
+
+fn vmc_loop(listen_port: u16, sender: SyncSender<VmcEvent>, handle: LoopHandle) {
+    // phone apps (MeowFace, iFacialMocap relays) send from the network, not just this machine
+    let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), listen_port);
+    let Some(listener) = bind_udp("VMC", addr) else {
+        return;
+    };
+
+    let mut parser = VmcParser::default();
+    let mut buf = [0u8; rosc::decoder::MTU];
+    while !handle.is_stopped() {
+        let size = match listener.recv(&mut buf) {
+            Ok(size) => size,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                log::warn!("VMC: failed to receive a packet: {}", e);
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..size]) else {
+            continue;
+        };
+        let mut events = Vec::new();
+        parser.packet(packet, &mut events);
+        for event in events {
+            if let Err(e) = sender.try_send(event) {
+                log::warn!("Failed to send VMC message: {}", e);
+            }
+        }
+    }
+}
+
+/// Collects blendshape values until the sender applies them.
+/// Values that are not re-sent keep their last value.
+struct VmcParser {
+    values: Box<ArkitShapes>,
+    dirty: bool,
+}
+
+impl Default for VmcParser {
+    fn default() -> Self {
+        Self {
+            values: Box::new([0.; ArkitShape::COUNT]),
+            dirty: false,
+        }
+    }
+}
+
+impl VmcParser {
+    fn packet(&mut self, packet: OscPacket, events: &mut Vec<VmcEvent>) {
+        match packet {
+            OscPacket::Message(msg) => self.message(msg, events),
+            OscPacket::Bundle(bundle) => bundle
+                .content
+                .into_iter()
+                .for_each(|packet| self.packet(packet, events)),
+        }
+    }
+
+    fn message(&mut self, msg: OscMessage, events: &mut Vec<VmcEvent>) {
+        match (msg.addr.as_str(), &msg.args[..]) {
+            ("/VMC/Ext/Blend/Val", [OscType::String(name), OscType::Float(value)]) => {
+                if let Ok(shape) = ArkitShape::from_str(name) {
+                    self.values[shape as usize] = *value;
+                    self.dirty = true;
+                }
+            }
+            ("/VMC/Ext/Blend/Apply", _) if self.dirty => {
+                self.dirty = false;
+                events.push(VmcEvent::Blendshapes(self.values.clone()));
+            }
+            (
+                "/VMC/Ext/Bone/Pos",
+                [OscType::String(bone), _, _, _, OscType::Float(x), OscType::Float(y), OscType::Float(z), OscType::Float(w)],
+            ) => {
+                let rotation = unity_quat_to_rotation(Quat::from_xyzw(*x, *y, *z, *w));
+                match bone.as_str() {
+                    "Head" => events.push(VmcEvent::Head(rotation)),
+                    "LeftEye" => events.push(VmcEvent::Eye(0, rotation)),
+                    "RightEye" => events.push(VmcEvent::Eye(1, rotation)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(addr: &str, args: Vec<OscType>) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        })
+    }
+
+    fn blend(name: &str, value: f32) -> OscPacket {
+        msg(
+            "/VMC/Ext/Blend/Val",
+            vec![OscType::String(name.to_string()), OscType::Float(value)],
+        )
+    }
+
+    #[test]
+    fn applies_blendshapes_in_batches() {
+        let mut parser = VmcParser::default();
+        let mut events = Vec::new();
+
+        parser.packet(blend("jawOpen", 0.5), &mut events);
+        parser.packet(blend("EyeBlinkLeft", 1.), &mut events);
+        parser.packet(blend("NotAShape", 1.), &mut events);
+        assert!(events.is_empty());
+
+        parser.packet(msg("/VMC/Ext/Blend/Apply", vec![]), &mut events);
+        let [VmcEvent::Blendshapes(arkit)] = &events[..] else {
+            panic!("unexpected events: {:?}", events);
+        };
+        assert_eq!(arkit[ArkitShape::JawOpen as usize], 0.5);
+        assert_eq!(arkit[ArkitShape::EyeBlinkLeft as usize], 1.);
+
+        // nothing new since the last Apply
+        parser.packet(msg("/VMC/Ext/Blend/Apply", vec![]), &mut events);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn converts_head_bone_from_unity() {
+        let mut parser = VmcParser::default();
+        let mut events = Vec::new();
+
+        // turned right in Unity's left-handed space
+        let unity = Quat::from_rotation_y(0.5);
+        let mut args = vec![OscType::String("Head".into())];
+        args.extend([0., 1.5, 0.].map(OscType::Float));
+        args.extend(unity.to_array().map(OscType::Float));
+        parser.packet(msg("/VMC/Ext/Bone/Pos", args), &mut events);
+
+        let [VmcEvent::Head(rotation)] = events[..] else {
+            panic!("unexpected events: {:?}", events);
+        };
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_y(-0.5), 1e-6));
+    }
+}
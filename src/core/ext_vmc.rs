@@ -0,0 +1,210 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+use anyhow::Context;
+use glam::{EulerRot, Quat};
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+use strum::IntoEnumIterator;
+
+use super::{
+    bundle::AvatarBundle,
+    config::{VmcConfig, VmcPreset},
+    ext_tracking::{
+        arkit::{eye_gaze, unified_to_arkit, ArkitShape, ArkitShapes, NUM_ARKIT_BLENDSHAPES},
+        unified::UnifiedTrackingData,
+    },
+    tracking_input::unity_quat_to_rotation,
+};
+
+/// Sends face tracking to a VMC protocol receiver, such as VSeeFace, VNyan or Warudo.
+pub struct ExtVmc {
+    socket: UdpSocket,
+    preset: VmcPreset,
+    start: Instant,
+}
+
+impl ExtVmc {
+    pub fn new(config: &VmcConfig) -> anyhow::Result<Self> {
+        let bind = match config.target {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+        let socket = UdpSocket::bind(bind).context("Could not bind VMC socket")?;
+        socket
+            .connect(config.target)
+            .with_context(|| format!("Could not connect to {}", config.target))?;
+
+        log::info!(
+            "Sending VMC to {} with {:?} blendshapes",
+            config.target,
+            config.preset
+        );
+
+        Ok(Self {
+            socket,
+            preset: config.preset,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn step(&mut self, data: &UnifiedTrackingData) {
+        let mut bundle = OscBundle::new_bundle();
+        self.write_frame(data, &mut bundle);
+
+        // receivers handle the bundles in order, so Apply still comes last
+        for chunk in bundle.content.chunks(30) {
+            let mut bundle = OscBundle::new_bundle();
+            bundle.content.extend_from_slice(chunk);
+            bundle
+                .serialize()
+                .and_then(|buf| self.socket.send(&buf).ok());
+        }
+    }
+
+    fn write_frame(&self, data: &UnifiedTrackingData, bundle: &mut OscBundle) {
+        send(bundle, "/VMC/Ext/OK", vec![OscType::Int(1)]);
+        send(
+            bundle,
+            "/VMC/Ext/T",
+            vec![OscType::Float(self.start.elapsed().as_secs_f32())],
+        );
+
+        for (eye, bone) in ["LeftEye", "RightEye"].into_iter().enumerate() {
+            let (pitch, yaw) = eye_gaze(data, eye);
+            let rotation = unity_quat_to_rotation(Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.));
+            let mut args = vec![OscType::String(bone.to_string())];
+            args.extend([0., 0., 0.].map(OscType::Float));
+            args.extend(rotation.to_array().map(OscType::Float));
+            send(bundle, "/VMC/Ext/Bone/Pos", args);
+        }
+
+        let arkit = unified_to_arkit(data);
+        let shapes: Vec<(&str, f32)> = match self.preset {
+            VmcPreset::Arkit => ArkitShape::iter()
+                .take(NUM_ARKIT_BLENDSHAPES)
+                .map(|shape| (shape.into(), arkit[shape as usize]))
+                .collect(),
+            VmcPreset::Vrm => vrm_clips(&arkit),
+        };
+        for (name, value) in shapes {
+            send(
+                bundle,
+                "/VMC/Ext/Blend/Val",
+                vec![OscType::String(name.to_string()), OscType::Float(value)],
+            );
+        }
+        send(bundle, "/VMC/Ext/Blend/Apply", vec![]);
+    }
+}
+
+fn send(bundle: &mut OscBundle, addr: &str, args: Vec<OscType>) {
+    bundle.content.push(OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
+    }));
+}
+
+/// Approximates the VRM 0.x preset clips, for avatars without perfect sync.
+fn vrm_clips(arkit: &ArkitShapes) -> Vec<(&'static str, f32)> {
+    use ArkitShape as A;
+
+    let a = |shape: A| arkit[shape as usize];
+    let avg = |l: A, r: A| (a(l) + a(r)) * 0.5;
+
+    vec![
+        ("A", a(A::JawOpen)),
+        ("I", avg(A::MouthStretchLeft, A::MouthStretchRight)),
+        ("U", a(A::MouthPucker)),
+        ("E", avg(A::MouthLowerDownLeft, A::MouthLowerDownRight)),
+        ("O", a(A::MouthFunnel)),
+        ("Blink_L", a(A::EyeBlinkLeft)),
+        ("Blink_R", a(A::EyeBlinkRight)),
+        ("Joy", avg(A::MouthSmileLeft, A::MouthSmileRight)),
+        ("Angry", avg(A::BrowDownLeft, A::BrowDownRight)),
+        ("Sorrow", avg(A::MouthFrownLeft, A::MouthFrownRight)),
+        ("LookUp", avg(A::EyeLookUpLeft, A::EyeLookUpRight)),
+        ("LookDown", avg(A::EyeLookDownLeft, A::EyeLookDownRight)),
+        ("LookLeft", avg(A::EyeLookOutLeft, A::EyeLookInRight)),
+        ("LookRight", avg(A::EyeLookInLeft, A::EyeLookOutRight)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ext_tracking::unified::UnifiedExpressions;
+
+    fn frame(preset: VmcPreset, data: &UnifiedTrackingData) -> Vec<OscMessage> {
+        let vmc = ExtVmc::new(&VmcConfig {
+            enabled: true,
+            preset,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut bundle = OscBundle::new_bundle();
+        vmc.write_frame(data, &mut bundle);
+        bundle
+            .content
+            .into_iter()
+            .filter_map(|packet| match packet {
+                OscPacket::Message(msg) => Some(msg),
+                OscPacket::Bundle(_) => None,
+            })
+            .collect()
+    }
+
+    fn blend(messages: &[OscMessage], name: &str) -> Option<f32> {
+        messages.iter().find_map(|msg| match &msg.args[..] {
+            [OscType::String(n), OscType::Float(v)]
+                if msg.addr == "/VMC/Ext/Blend/Val" && n == name =>
+            {
+                Some(*v)
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn sends_blendshapes_then_apply() {
+        let mut data = UnifiedTrackingData::default();
+        data.setu(UnifiedExpressions::JawOpen, 0.6);
+
+        let arkit = frame(VmcPreset::Arkit, &data);
+        assert_eq!(blend(&arkit, "JawOpen"), Some(0.6));
+        assert_eq!(
+            arkit
+                .iter()
+                .filter(|msg| msg.addr == "/VMC/Ext/Blend/Val")
+                .count(),
+            NUM_ARKIT_BLENDSHAPES
+        );
+        assert_eq!(arkit.last().unwrap().addr, "/VMC/Ext/Blend/Apply");
+
+        let vrm = frame(VmcPreset::Vrm, &data);
+        assert_eq!(blend(&vrm, "A"), Some(0.6));
+        assert_eq!(blend(&vrm, "JawOpen"), None);
+    }
+
+    #[test]
+    fn eye_bones_follow_gaze() {
+        let mut data = UnifiedTrackingData::default();
+        data.eyes[0] = Some(glam::Vec3::new(0., 0.3, 0.));
+
+        let messages = frame(VmcPreset::Arkit, &data);
+        let bone = messages
+            .iter()
+            .find(|msg| msg.addr == "/VMC/Ext/Bone/Pos")
+            .unwrap();
+        assert_eq!(bone.args[0], OscType::String("LeftEye".into()));
+        let [OscType::Float(x), OscType::Float(y), OscType::Float(z), OscType::Float(w)] =
+            bone.args[4..8]
+        else {
+            panic!("bad rotation: {:?}", bone.args);
+        };
+        // Unity is left-handed, so looking left is a negative turn around Y
+        let rotation = Quat::from_xyzw(x, y, z, w);
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_y(-0.3), 1e-6));
+    }
+}
@@ -0,0 +1,352 @@
+//! Runs a real `AvatarOsc` against a fake VRChat, so the extensions can be tested
+//! end to end without a headset.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde_json::json;
+
+use crate::Args;
+
+use super::{config::Config, AvatarOsc, PARAM_PREFIX};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A new empty folder on every call, so that tests running in parallel don't share
+/// extMem.json and friends, and nothing touches the real config folder.
+pub fn config_dir() -> Arc<str> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "oscavmgr-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    // left over from an earlier run with the same pid
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create test config dir");
+    dir.to_str().expect("temp dir is UTF-8").into()
+}
+
+/// Plays the part of VRChat: owns the port oscavmgr sends to, and serves `/avatar`.
+pub struct FakeVrc {
+    socket: UdpSocket,
+    osc_addr: SocketAddr,
+    avatar_json: Arc<Mutex<String>>,
+}
+
+impl FakeVrc {
+    /// Starts oscavmgr on ephemeral ports, in its own config folder.
+    /// `configure` can change anything but the ports and folders.
+    pub fn start(configure: impl FnOnce(&mut Config)) -> Self {
+        let config_dir = config_dir();
+        let args = Args {
+            config: Some(format!("{}/oscavmgr.toml", config_dir)),
+            ..Args::default()
+        };
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        let avatar_json = Arc::new(Mutex::new(avatar_json(&[])));
+        let http_port = serve_oscquery(avatar_json.clone());
+
+        let mut config = Config::default();
+        // both reshape the face over time, which would make the values sent depend on timing
+        config.tracking_loss.enabled = false;
+        config.calibration.enabled = false;
+        configure(&mut config);
+        config.osc.vrc_port = socket.local_addr().unwrap().port();
+        config.osc.osc_port = 0;
+        config.osc.advertise = false;
+        config.osc.oscquery_url = Some(format!("http://127.0.0.1:{}", http_port));
+        // an empty stand-in for VRChat's OSC folder, instead of the one on this machine
+        let vrchat_osc_dir = format!("{}/OSC", config_dir);
+        std::fs::create_dir_all(&vrchat_osc_dir).expect("create test OSC dir");
+        config.osc.vrchat_osc_dir = Some(vrchat_osc_dir);
+
+        // the receivers are not Send, so AvatarOsc has to be built on its own thread
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let multi = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+            let mut osc = AvatarOsc::with_config_dir(config, args, config_dir, multi);
+            sender.send(osc.local_addr()).unwrap();
+            osc.handle_messages();
+        });
+        let osc_addr = receiver
+            .recv_timeout(TIMEOUT)
+            .expect("oscavmgr did not start");
+
+        Self {
+            socket,
+            osc_addr,
+            avatar_json,
+        }
+    }
+
+    pub fn send(&self, addr: &str, args: Vec<OscType>) {
+        let buf = rosc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        }))
+        .unwrap();
+        self.socket.send_to(&buf, self.osc_addr).unwrap();
+    }
+
+    pub fn send_param(&self, name: &str, value: OscType) {
+        self.send(&format!("{}{}", PARAM_PREFIX, name), vec![value]);
+    }
+
+    /// What the VSync layer of an avatar does each animator frame.
+    pub fn vsync(&self) {
+        self.send_param("VSync", OscType::Bool(true));
+    }
+
+    /// Switches to an avatar whose `/avatar` tree is `json`, see [`avatar_json`].
+    pub fn change_avatar(&self, id: &str, json: String) {
+        *self.avatar_json.lock().unwrap() = json;
+        self.send("/avatar/change", vec![OscType::String(id.to_string())]);
+    }
+
+    /// Feeds every message oscavmgr sends to `done` until it returns true.
+    pub fn recv_until(&self, mut done: impl FnMut(&OscMessage) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut buf = [0u8; rosc::decoder::MTU];
+        while Instant::now() < deadline {
+            let Ok(size) = self.socket.recv(&mut buf) else {
+                continue;
+            };
+            let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..size]) else {
+                continue;
+            };
+            let mut messages = Vec::new();
+            flatten(packet, &mut messages);
+            if messages.iter().any(&mut done) {
+                return;
+            }
+        }
+        panic!("timed out waiting for oscavmgr");
+    }
+
+    /// Waits for a message to `addr` and returns its arguments.
+    pub fn expect(&self, addr: &str) -> Vec<OscType> {
+        let mut args = None;
+        self.recv_until(|msg| {
+            if msg.addr == addr {
+                args = Some(msg.args.clone());
+            }
+            args.is_some()
+        });
+        args.unwrap()
+    }
+
+    pub fn expect_param(&self, name: &str) -> OscType {
+        self.expect(&format!("{}{}", PARAM_PREFIX, name))
+            .into_iter()
+            .next()
+            .expect("parameter without a value")
+    }
+}
+
+fn flatten(packet: OscPacket, out: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(msg) => out.push(msg),
+        OscPacket::Bundle(bundle) => bundle
+            .content
+            .into_iter()
+            .for_each(|packet| flatten(packet, out)),
+    }
+}
+
+/// Answers every HTTP request with the current avatar tree, like VRChat's OSCQuery server.
+pub fn serve_oscquery(avatar_json: Arc<Mutex<String>>) -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let Ok(read_half) = stream.try_clone() else {
+                continue;
+            };
+            let mut reader = BufReader::new(read_half);
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+
+            let body = avatar_json.lock().unwrap().clone();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+
+    port
+}
+
+/// An OSCQuery `/avatar` tree with the given `(parameter, OSC type tag)` pairs,
+/// e.g. `("FT/v2/JawOpen1", "T")`.
+pub fn avatar_json(params: &[(&str, &str)]) -> String {
+    let mut root = json!({
+        "FULL_PATH": "/avatar",
+        "ACCESS": 0,
+        "CONTENTS": {
+            "parameters": {
+                "FULL_PATH": "/avatar/parameters",
+                "ACCESS": 0,
+                "CONTENTS": {},
+            },
+        },
+    });
+
+    for (name, osc_type) in params.iter() {
+        let mut node = &mut root["CONTENTS"]["parameters"];
+        let mut path = String::from("/avatar/parameters");
+        let parts: Vec<&str> = name.split('/').collect();
+
+        for (i, part) in parts.iter().enumerate() {
+            path = format!("{}/{}", path, part);
+            let child = &mut node["CONTENTS"][*part];
+            if child.is_null() {
+                *child = if i + 1 == parts.len() {
+                    json!({ "FULL_PATH": path, "ACCESS": 3, "TYPE": osc_type })
+                } else {
+                    json!({ "FULL_PATH": path, "ACCESS": 0, "CONTENTS": {} })
+                };
+            }
+            node = child;
+        }
+    }
+
+    root.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Affine3A;
+
+    use super::*;
+    use crate::{
+        core::{
+            ext_tracking::{
+                recording::Recorder,
+                unified::{UnifiedExpressions, UnifiedTrackingData},
+            },
+            OscTrack,
+        },
+        FaceSetup,
+    };
+
+    #[test]
+    fn gogo_restores_poses_and_switches_locomotion() {
+        let vrc = FakeVrc::start(|_| {});
+
+        vrc.change_avatar(
+            "avtr_gogo",
+            avatar_json(&[("VSync", "T"), ("TrackingType", "i")]),
+        );
+        assert!(matches!(vrc.expect_param("Go/StandIdle"), OscType::Int(_)));
+
+        // full body
+        vrc.send_param("TrackingType", OscType::Int(6));
+        vrc.vsync();
+        assert_eq!(vrc.expect_param("Go/Locomotion"), OscType::Bool(true));
+    }
+
+    #[test]
+    fn storage_plays_back_saved_values() {
+        let vrc = FakeVrc::start(|config| config.storage.tick_ms = 10);
+
+        vrc.send_param("ExtIndex", OscType::Int(5));
+        vrc.send_param("ExtValue", OscType::Float(0.7));
+        vrc.send_param("ExtIndex", OscType::Int(0));
+
+        let mut index = None;
+        let mut value = None;
+        vrc.recv_until(|msg| {
+            match (msg.addr.strip_prefix(PARAM_PREFIX), &msg.args[..]) {
+                (Some("IntIndex"), [OscType::Int(i)]) => index = Some(*i),
+                (Some("IntValue"), [OscType::Float(v)]) if index == Some(5) => value = Some(*v),
+                _ => {}
+            }
+            value.is_some()
+        });
+        assert_eq!(value, Some(0.7));
+    }
+
+    #[test]
+    fn autopilot_jumps_with_hands_above_head() {
+        let vrc = FakeVrc::start(|_| {});
+
+        vrc.send_param("VRCEmote", OscType::Int(120));
+        let pose = |x: f32, y: f32| [x, y, 0., 0., 0., 0.].map(OscType::Float).to_vec();
+        vrc.send("/tracking/vrsystem/head/pose", pose(0., 1.6));
+        vrc.send("/tracking/vrsystem/leftwrist/pose", pose(-0.3, 1.9));
+        vrc.send("/tracking/vrsystem/rightwrist/pose", pose(0.3, 1.9));
+
+        assert_eq!(vrc.expect("/input/Jump"), vec![OscType::Bool(true)]);
+    }
+
+    #[test]
+    fn face_params_are_sent_as_binary() {
+        let file = format!("{}/jaw-open.oamr", config_dir());
+        {
+            let mut data = UnifiedTrackingData::default();
+            // 0.72 * 7 = 5 = 0b101
+            data.setu(UnifiedExpressions::JawOpen, 0.72);
+            let tracking = OscTrack {
+                head: Affine3A::IDENTITY,
+                left_hand: Affine3A::IDENTITY,
+                right_hand: Affine3A::IDENTITY,
+                last_received: Instant::now(),
+            };
+            let mut recorder = Recorder::create(&file).unwrap();
+            recorder.record(&data, &tracking).unwrap();
+        }
+
+        let vrc = FakeVrc::start(|config| {
+            config.face = FaceSetup::Replay {
+                file,
+                speed: 1.0,
+                looping: true,
+            }
+        });
+        vrc.change_avatar(
+            "avtr_ft",
+            avatar_json(&[
+                ("FT/v2/JawOpen1", "T"),
+                ("FT/v2/JawOpen2", "T"),
+                ("FT/v2/JawOpen4", "T"),
+            ]),
+        );
+
+        let mut bits = [None; 3];
+        vrc.recv_until(|msg| {
+            let bit = match msg.addr.strip_prefix(PARAM_PREFIX) {
+                Some("FT/v2/JawOpen1") => 0,
+                Some("FT/v2/JawOpen2") => 1,
+                Some("FT/v2/JawOpen4") => 2,
+                _ => return false,
+            };
+            bits[bit] = msg.args.first().cloned();
+            bits[0].is_some() && bits[2].is_some()
+        });
+        assert_eq!(bits[0], Some(OscType::Bool(true)));
+        assert_eq!(bits[1], None);
+        assert_eq!(bits[2], Some(OscType::Bool(true)));
+    }
+}
@@ -1,13 +1,13 @@
 use colored::{Color, Colorize};
-use ext_oscjson::AvatarIdentifier;
-use glam::{Affine3A, Quat, Vec3};
+use ext_oscjson::{AvatarIdentifier, OscJsonEvent, OscJsonNode};
+use glam::Affine3A;
 use indicatif::MultiProgress;
 use log::info;
 use once_cell::sync::Lazy;
 use rosc::{OscBundle, OscPacket, OscType};
 use std::{
     collections::HashMap,
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -18,25 +18,38 @@ use std::{
 
 use crate::Args;
 
-use self::bundle::AvatarBundle;
+use self::{
+    bundle::AvatarBundle,
+    config::{Config, VmcConfig, WatchedFile},
+    folders::CONFIG_DIR,
+};
 
+mod avatar_files;
 mod bundle;
+pub mod config;
 mod ext_autopilot;
 mod ext_gogo;
 mod ext_oscjson;
 mod ext_storage;
 mod ext_tracking;
+mod ext_vmc;
 mod folders;
+#[cfg(test)]
+mod harness;
+mod oscjson_worker;
 mod oscquery;
+pub mod router;
+mod tracking_input;
 mod watchdog;
 
 pub mod status;
 
 pub const PARAM_PREFIX: &str = "/avatar/parameters/";
 const AVATAR_PREFIX: &str = "/avatar/change";
-const TRACK_PREFIX: &str = "/tracking/trackers/";
 const INPUT_PREFIX: &str = "/input/";
 
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub type AvatarParameters = HashMap<Arc<str>, OscType>;
 
 pub struct AppState {
@@ -49,16 +62,23 @@ pub struct AppState {
 
 pub struct AvatarOsc {
     ip: IpAddr,
-    osc_port: u16,
+    listener: UdpSocket,
     upstream: UdpSocket,
     ext_autopilot: ext_autopilot::ExtAutoPilot,
     ext_oscjson: ext_oscjson::ExtOscJson,
     ext_storage: ext_storage::ExtStorage,
     ext_gogo: ext_gogo::ExtGogo,
     ext_tracking: ext_tracking::ExtTracking,
-    _oscquery: Option<oscquery::OscQueryAdvert>,
+    ext_vmc: Option<ext_vmc::ExtVmc>,
+    oscquery: Option<oscquery::OscQueryAdvert>,
+    router: router::OscRouter,
     multi: MultiProgress,
-    avatar_file: Option<String>,
+    config: Config,
+    args: Args,
+    config_file: WatchedFile,
+    avatar_file: Option<WatchedFile>,
+    next_reload_check: Instant,
+    recorder: Option<ext_tracking::recording::Recorder>,
 }
 
 pub struct OscTrack {
@@ -68,58 +88,138 @@ pub struct OscTrack {
     pub last_received: Instant,
 }
 
+impl OscTrack {
+    pub fn get(&self, device: tracking_input::TrackedDevice) -> Affine3A {
+        match device {
+            tracking_input::TrackedDevice::Head => self.head,
+            tracking_input::TrackedDevice::LeftWrist => self.left_hand,
+            tracking_input::TrackedDevice::RightWrist => self.right_hand,
+        }
+    }
+
+    pub fn set(&mut self, device: tracking_input::TrackedDevice, transform: Affine3A) {
+        match device {
+            tracking_input::TrackedDevice::Head => self.head = transform,
+            tracking_input::TrackedDevice::LeftWrist => self.left_hand = transform,
+            tracking_input::TrackedDevice::RightWrist => self.right_hand = transform,
+        }
+    }
+}
+
 impl AvatarOsc {
-    pub fn new(args: Args, multi: MultiProgress) -> AvatarOsc {
-        let ip = IpAddr::V4(if args.expose {
-            Ipv4Addr::UNSPECIFIED
-        } else {
-            Ipv4Addr::LOCALHOST
+    pub fn new(config: Config, args: Args, multi: MultiProgress) -> AvatarOsc {
+        Self::with_config_dir(config, args, CONFIG_DIR.clone(), multi)
+    }
+
+    /// Keeps the extensions' files (extMem.json, calibrations, cached avatars...) in `config_dir`.
+    pub fn with_config_dir(
+        config: Config,
+        args: Args,
+        config_dir: Arc<str>,
+        multi: MultiProgress,
+    ) -> AvatarOsc {
+        let ip = config.osc.bind_ip().unwrap_or_else(|e| {
+            log::warn!("{e:#}, listening on localhost");
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
         });
 
-        let upstream = UdpSocket::bind("0.0.0.0:0").expect("bind upstream socket");
-        upstream
-            .connect(SocketAddr::new(ip, args.vrc_port))
-            .expect("upstream connect");
-
-        let ext_autopilot = ext_autopilot::ExtAutoPilot::new();
-        let ext_storage = ext_storage::ExtStorage::new();
-        let ext_gogo = ext_gogo::ExtGogo::new();
-        let ext_tracking = ext_tracking::ExtTracking::new(args.face);
-        let ext_oscjson = ext_oscjson::ExtOscJson::new();
-        let oscquery = match oscquery::OscQueryAdvert::new(args.osc_port) {
-            Ok(advert) => Some(advert),
-            Err(e) => {
-                log::warn!("Failed to start OSCQuery: {e:#}");
-                None
+        // with an explicit bind, send from that address too
+        let source = match config.osc.bind {
+            Some(_) => ip,
+            None => unspecified(ip),
+        };
+        let upstream = UdpSocket::bind(SocketAddr::new(source, 0)).expect("bind upstream socket");
+        match config.osc.vrc_addr(ip) {
+            Some(addr) => upstream.connect(addr).expect("upstream connect"),
+            None => info!("Not sending OSC until VRChat is found, or osc.vrc_ip is set"),
+        }
+
+        let router = router::OscRouter::new(
+            &config.router.routes,
+            config
+                .router
+                .listen_port
+                .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)),
+            &upstream,
+        );
+
+        let ext_autopilot = ext_autopilot::ExtAutoPilot::new(&config.autopilot);
+        let ext_storage = ext_storage::ExtStorage::new(&config.storage, &config_dir);
+        let ext_gogo = ext_gogo::ExtGogo::new(&config_dir);
+        let ext_tracking = ext_tracking::ExtTracking::new(&config, config_dir.clone());
+        let ext_vmc = start_vmc(&config.vmc);
+        let listener = UdpSocket::bind(SocketAddr::new(ip, config.osc.osc_port))
+            .expect("bind listener socket");
+        let osc_port = listener.local_addr().expect("listener address").port();
+
+        let ext_oscjson = ext_oscjson::ExtOscJson::new(&config.osc, config_dir);
+        let oscquery = if config.osc.advertise {
+            match oscquery::OscQueryAdvert::new(ip, osc_port) {
+                Ok(advert) => Some(advert),
+                Err(e) => {
+                    log::warn!("Failed to start OSCQuery: {e:#}");
+                    None
+                }
             }
+        } else {
+            None
         };
 
+        let recorder = args.record.as_ref().and_then(|path| {
+            match ext_tracking::recording::Recorder::create(path) {
+                Ok(recorder) => {
+                    info!("Recording session to {}", path);
+                    Some(recorder)
+                }
+                Err(e) => {
+                    log::warn!("Not recording: {e:#}");
+                    None
+                }
+            }
+        });
+
+        let mut config_file = WatchedFile::new(Config::path(&args));
+        config_file.changed(); // already loaded by main
+
         AvatarOsc {
             ip,
-            osc_port: args.osc_port,
+            listener,
             upstream,
             ext_autopilot,
             ext_oscjson,
             ext_storage,
             ext_gogo,
             ext_tracking,
-            _oscquery: oscquery,
+            ext_vmc,
+            oscquery,
+            router,
             multi,
-            avatar_file: args.avatar,
+            avatar_file: config.avatar.clone().map(WatchedFile::new),
+            config,
+            args,
+            config_file,
+            next_reload_check: Instant::now(),
+            recorder,
         }
     }
 
+    /// Where oscavmgr receives OSC, useful when `osc_port` is 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().expect("listener address")
+    }
+
     pub fn send_upstream(&self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.config.vmc.replace_upstream {
+            return Ok(0);
+        }
         self.upstream.send(buf)
     }
 
     pub fn handle_messages(&mut self) {
-        let ip = self.ip;
-        let listener =
-            UdpSocket::bind(SocketAddr::new(ip, self.osc_port)).expect("bind listener socket");
-
-        let lo = UdpSocket::bind("0.0.0.0:0").expect("bind self socket");
-        lo.connect(SocketAddr::new(ip, self.osc_port)).unwrap();
+        let lo =
+            UdpSocket::bind(SocketAddr::new(unspecified(self.ip), 0)).expect("bind self socket");
+        lo.connect(SocketAddr::new(self.ip, self.local_addr().port()))
+            .unwrap();
         let lo_addr = lo.local_addr().unwrap();
 
         let mut state = AppState {
@@ -149,15 +249,12 @@ impl AvatarOsc {
             }
         });
 
-        info!(
-            "Listening for OSC messages on {}",
-            listener.local_addr().unwrap()
-        );
+        info!("Listening for OSC messages on {}", self.local_addr());
 
         let mut last_frame = Instant::now();
         let mut buf = [0u8; rosc::decoder::MTU];
         loop {
-            if let Ok((size, addr)) = listener.recv_from(&mut buf) {
+            if let Ok((size, addr)) = self.listener.recv_from(&mut buf) {
                 if addr == lo_addr {
                     self.process(&mut state);
                     watchdog.update();
@@ -166,8 +263,12 @@ impl AvatarOsc {
                     continue;
                 }
 
-                if let Ok((_, OscPacket::Message(packet))) = rosc::decoder::decode_udp(&buf[..size])
-                {
+                let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..size]) else {
+                    continue;
+                };
+                self.router.forward(&buf[..size], &packet);
+
+                if let OscPacket::Message(packet) = packet {
                     state.status.trip_recv_counter();
                     if packet.addr.starts_with(PARAM_PREFIX) {
                         let name: Arc<str> = packet.addr[PARAM_PREFIX.len()..].into();
@@ -182,29 +283,11 @@ impl AvatarOsc {
                             self.ext_gogo.notify(&name, &arg);
                             state.params.insert(name, arg);
                         }
-                    } else if packet.addr.starts_with(TRACK_PREFIX) {
-                        if let [OscType::Float(x), OscType::Float(y), OscType::Float(z), OscType::Float(ex), OscType::Float(ey), OscType::Float(ez)] =
-                            packet.args[..]
-                        {
-                            let transform = Affine3A::from_rotation_translation(
-                                Quat::from_euler(glam::EulerRot::ZXY, ex, ey, ez),
-                                Vec3::new(x, y, z),
-                            );
-
-                            if packet.addr[TRACK_PREFIX.len()..].starts_with("head") {
-                                state.tracking.last_received = Instant::now();
-                                state.tracking.head = transform;
-                            } else if packet.addr[TRACK_PREFIX.len()..].starts_with("leftwrist") {
-                                state.tracking.left_hand = transform;
-                            } else if packet.addr[TRACK_PREFIX.len()..].starts_with("rightwrist") {
-                                state.tracking.right_hand = transform;
-                            }
-                        }
                     } else if packet.addr.starts_with(AVATAR_PREFIX) {
                         if let [OscType::String(avatar)] = &packet.args[..] {
                             self.avatar(AvatarIdentifier::Uid(avatar.clone()), &mut state);
                         }
-                    } else {
+                    } else if !tracking_input::handle(&packet, &mut state.tracking) {
                         log::info!("Received data: {:?}", packet);
                     }
                 }
@@ -214,17 +297,30 @@ impl AvatarOsc {
 
     fn avatar(&mut self, avatar: AvatarIdentifier, state: &mut AppState) {
         info!("Avatar changed: {:?}", avatar);
-        let osc_root_node = self.ext_oscjson.avatar(&avatar);
-        if let Some(osc_root_node) = osc_root_node.as_ref() {
-            self.ext_tracking.osc_json(osc_root_node);
+        if let AvatarIdentifier::Uid(id) = &avatar {
+            self.ext_tracking.set_avatar_id(id);
         }
+        let osc_root_node = self.ext_oscjson.avatar(&avatar);
 
         let mut bundle = OscBundle::new_bundle();
         self.ext_gogo.avatar(&mut bundle);
+        if let Some(oscquery) = self.oscquery.as_ref() {
+            oscquery.clear();
+            oscquery.record(&bundle);
+        }
         bundle
             .serialize()
             .and_then(|buf| self.send_upstream(&buf).ok());
 
+        self.avatar_tree(osc_root_node.as_ref(), state);
+    }
+
+    /// Maps the avatar's parameters, which may only arrive a while after the avatar change.
+    fn avatar_tree(&mut self, osc_root_node: Option<&OscJsonNode>, state: &mut AppState) {
+        if let Some(osc_root_node) = osc_root_node {
+            self.ext_tracking.osc_json(osc_root_node);
+        }
+
         state.self_drive.store(
             !osc_root_node.is_some_and(|n| {
                 let has_vsync = n.has_vsync();
@@ -254,6 +350,64 @@ impl AvatarOsc {
         );
     }
 
+    fn reload_files(&mut self, state: &mut AppState) {
+        if self.config_file.changed() {
+            match Config::load(&self.args) {
+                Ok(config) => self.reconfigure(config),
+                Err(e) => log::warn!("Config not reloaded: {e:#}"),
+            }
+        }
+
+        let Some(avatar_file) = self.avatar_file.as_mut() else {
+            return;
+        };
+        let first_load = !avatar_file.seen();
+        if !avatar_file.changed() {
+            return;
+        }
+
+        let avatar = AvatarIdentifier::Path(avatar_file.path().to_string());
+        if first_load {
+            self.avatar(avatar, state);
+        } else {
+            // same avatar, new mapping: leave gogo and vsync state alone
+            info!("Avatar file changed, reloading parameter mapping");
+            if let Some(osc_root_node) = self.ext_oscjson.avatar(&avatar) {
+                self.ext_tracking.osc_json(&osc_root_node);
+            }
+        }
+    }
+
+    fn reconfigure(&mut self, config: Config) {
+        let changes = self.config.diff(&config);
+        if changes.is_empty() {
+            return;
+        }
+
+        info!("Config file changed:");
+        for change in changes.iter() {
+            info!("  {}", change);
+        }
+
+        if config.osc != self.config.osc || config.router != self.config.router {
+            log::warn!("Changes to [osc] and [router] take effect after a restart.");
+        }
+
+        self.ext_autopilot.reconfigure(&config.autopilot);
+        self.ext_storage.reconfigure(&config.storage);
+        self.ext_tracking.reconfigure(&config);
+        if config.vmc != self.config.vmc {
+            self.ext_vmc = start_vmc(&config.vmc);
+        }
+
+        if config.avatar != self.config.avatar {
+            // picked up by the next reload_files
+            self.avatar_file = config.avatar.clone().map(WatchedFile::new);
+        }
+
+        self.config = config;
+    }
+
     fn process(&mut self, state: &mut AppState) {
         let mut bundle = OscBundle::new_bundle();
 
@@ -271,17 +425,42 @@ impl AvatarOsc {
             },
         );
 
-        if self.ext_oscjson.step() {
-            self.avatar(AvatarIdentifier::Default, state);
-        } else if let Some(path) = self.avatar_file.take() {
-            self.avatar(AvatarIdentifier::Path(path.clone()), state);
+        if let Some(event) = self.ext_oscjson.step() {
+            match event {
+                OscJsonEvent::Lookup => self.avatar(AvatarIdentifier::Default, state),
+                // same avatar, new mapping: leave gogo state alone
+                OscJsonEvent::Refreshed(osc_root_node) => {
+                    self.avatar_tree(Some(&osc_root_node), state)
+                }
+                OscJsonEvent::Upstream(addr) => {
+                    info!("Sending OSC to {}", addr);
+                    if let Err(e) = self.upstream.connect(addr) {
+                        log::warn!("Could not send OSC to {}: {}", addr, e);
+                    }
+                }
+            }
+        } else if self.next_reload_check <= Instant::now() {
+            self.next_reload_check = Instant::now() + RELOAD_CHECK_INTERVAL;
+            self.reload_files(state);
         }
 
         self.ext_storage.step(&mut bundle);
         self.ext_tracking.step(state, &mut bundle);
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.record(&self.ext_tracking.raw, &state.tracking) {
+                log::warn!("Recording to {} stopped: {}", recorder.path(), e);
+                self.recorder = None;
+            }
+        }
+        if let Some(vmc) = self.ext_vmc.as_mut() {
+            vmc.step(&self.ext_tracking.data);
+        }
         self.ext_gogo.step(&state.params, &mut bundle);
         self.ext_autopilot
             .step(state, &self.ext_tracking, &mut bundle);
+        if let Some(oscquery) = self.oscquery.as_ref() {
+            oscquery.record(&bundle);
+        }
 
         if let Some(packet) = bundle.content.first() {
             if let OscPacket::Message(..) = packet {
@@ -310,6 +489,27 @@ impl AvatarOsc {
     }
 }
 
+/// Any address of the same family as `ip`.
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+fn start_vmc(config: &VmcConfig) -> Option<ext_vmc::ExtVmc> {
+    if !config.enabled {
+        return None;
+    }
+    match ext_vmc::ExtVmc::new(config) {
+        Ok(vmc) => Some(vmc),
+        Err(e) => {
+            log::warn!("VMC output disabled: {e:#}");
+            None
+        }
+    }
+}
+
 static DRIVE_ON: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "DRIVE".color(Color::Blue)).into());
 static DRIVE_OFF: Lazy<Arc<str>> = Lazy::new(|| format!("{}", "VSYNC".color(Color::Green)).into());
 
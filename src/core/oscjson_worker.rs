@@ -0,0 +1,508 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use serde::Deserialize;
+
+use super::{
+    config::url_ip,
+    ext_oscjson::{parse_json, store_cached, OscJsonNode},
+};
+
+/// VRChat takes a moment to update `/avatar` after an avatar change
+const FIRST_FETCH_DELAY: Duration = Duration::from_millis(250);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+/// Doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 6;
+/// How often mDNS is checked while there is nothing to fetch
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SERVICE_TYPE: &str = "_oscjson._tcp.local.";
+/// Used when no client name is configured
+const VRCHAT_CLIENT: &str = "VRChat-Client-";
+
+pub enum WorkerEvent {
+    /// VRChat's OSCQuery server was found or configured, or another client was picked
+    Found,
+    /// The picked client takes OSC at this address
+    Upstream(SocketAddr),
+    /// The `/avatar` tree, for the avatar that was current when it was asked for
+    Fetched {
+        avatar_id: Option<String>,
+        node: OscJsonNode,
+    },
+}
+
+/// Finds VRChat through mDNS and fetches avatar trees on its own thread,
+/// so that the OSC thread never waits on the network.
+pub struct OscJsonWorker {
+    requests: Sender<Option<String>>,
+    events: Receiver<WorkerEvent>,
+}
+
+impl OscJsonWorker {
+    /// With `oscquery_url` set, mDNS is not used to find VRChat.
+    /// Otherwise, the newest client whose name starts with `client_name` is used.
+    /// Fetched trees are cached in `config_dir`.
+    pub fn start(
+        oscquery_url: Option<&str>,
+        client_name: Option<&str>,
+        config_dir: Arc<str>,
+    ) -> Self {
+        let (requests, request_receiver) = channel();
+        let (event_sender, events) = channel();
+
+        let fixed_url = oscquery_url.map(|url| url.trim_end_matches('/').to_string());
+        if fixed_url.is_some() {
+            let _ = event_sender.send(WorkerEvent::Found);
+        }
+        let mdns = if fixed_url.is_none() { browse() } else { None };
+
+        let mut worker = Worker::new(event_sender, client_name, config_dir);
+        worker.mdns = mdns;
+        worker.fixed_url = fixed_url;
+        thread::spawn(move || worker.run(request_receiver));
+
+        Self { requests, events }
+    }
+
+    /// Fetches the avatar tree as soon as VRChat can be reached, instead of any fetch still pending.
+    pub fn fetch(&self, avatar_id: Option<String>) {
+        let _ = self.requests.send(avatar_id);
+    }
+
+    pub fn try_recv(&self) -> Option<WorkerEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+fn browse() -> Option<(ServiceDaemon, mdns_sd::Receiver<ServiceEvent>)> {
+    let result = ServiceDaemon::new().and_then(|mdns| {
+        let receiver = mdns.browse(SERVICE_TYPE)?;
+        Ok((mdns, receiver))
+    });
+    match result {
+        Ok(browse) => Some(browse),
+        Err(e) => {
+            warn!("Could not look for VRChat through mDNS: {}", e);
+            None
+        }
+    }
+}
+
+struct Pending {
+    avatar_id: Option<String>,
+    next_attempt: Instant,
+    attempts: u32,
+}
+
+/// An OSCQuery server seen through mDNS.
+struct Client {
+    /// `http://HOST:PORT`
+    url: String,
+    /// Where it takes OSC, from its `HOST_INFO`
+    osc: Option<SocketAddr>,
+    /// Whether it is still announced
+    present: bool,
+    seen: Instant,
+}
+
+struct Worker {
+    mdns: Option<(ServiceDaemon, mdns_sd::Receiver<ServiceEvent>)>,
+    /// `osc.oscquery_url`, used instead of mDNS
+    fixed_url: Option<String>,
+    client_name: String,
+    /// Every client seen, by mDNS instance name
+    clients: HashMap<String, Client>,
+    selected: Option<String>,
+    upstream: Option<SocketAddr>,
+    http: reqwest::blocking::Client,
+    pending: Option<Pending>,
+    events: Sender<WorkerEvent>,
+    config_dir: Arc<str>,
+}
+
+impl Worker {
+    fn new(events: Sender<WorkerEvent>, client_name: Option<&str>, config_dir: Arc<str>) -> Self {
+        Self {
+            mdns: None,
+            fixed_url: None,
+            client_name: client_name.unwrap_or(VRCHAT_CLIENT).to_string(),
+            clients: HashMap::new(),
+            selected: None,
+            upstream: None,
+            http: reqwest::blocking::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .expect("HTTP client"),
+            pending: None,
+            events,
+            config_dir,
+        }
+    }
+
+    fn run(mut self, requests: Receiver<Option<String>>) {
+        if let Some(url) = self.fixed_url.clone() {
+            self.fixed_host_info(&url);
+        }
+        loop {
+            match requests.recv_timeout(POLL_INTERVAL) {
+                Ok(avatar_id) => {
+                    self.pending = Some(Pending {
+                        avatar_id,
+                        next_attempt: Instant::now() + FIRST_FETCH_DELAY,
+                        attempts: 0,
+                    })
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            self.discover();
+            self.try_fetch();
+        }
+    }
+
+    fn discover(&mut self) {
+        let Some((_, receiver)) = self.mdns.as_ref() else {
+            return;
+        };
+        let events: Vec<ServiceEvent> = receiver.try_iter().collect();
+        if events.is_empty() {
+            return;
+        }
+
+        for event in events {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    let name = instance_name(info.get_fullname());
+                    let Some(addr) = service_addr(info.get_addresses(), info.get_port()) else {
+                        debug!("No usable address for {}", name);
+                        continue;
+                    };
+                    let url = format!("http://{}", addr);
+                    info!("Found OSCJSON service: {} @ {}", name, url);
+
+                    let osc = match host_info(&self.http, &url) {
+                        Ok(osc) => Some(osc_target(osc, addr.ip())),
+                        Err(e) => {
+                            warn!("{}: {e:#}", name);
+                            None
+                        }
+                    };
+                    self.resolved(name, url, osc);
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    let name = instance_name(&fullname);
+                    if let Some(client) = self.clients.get_mut(name) {
+                        info!("OSCJSON service went away: {}", name);
+                        client.present = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.select();
+    }
+
+    /// Sends OSC where the configured OSCQuery server says, if it says.
+    fn fixed_host_info(&mut self, url: &str) {
+        match host_info(&self.http, url) {
+            Ok(osc) => {
+                let osc = url_ip(url).map_or(osc, |ip| osc_target(osc, ip));
+                self.upstream = Some(osc);
+                let _ = self.events.send(WorkerEvent::Upstream(osc));
+            }
+            Err(e) => debug!("{e:#}, keeping the configured OSC destination"),
+        }
+    }
+
+    fn resolved(&mut self, name: &str, url: String, osc: Option<SocketAddr>) {
+        self.clients.insert(
+            name.to_string(),
+            Client {
+                url,
+                osc,
+                present: true,
+                seen: Instant::now(),
+            },
+        );
+    }
+
+    /// Keeps the picked client while it is announced, then moves on to the newest one that matches.
+    fn select(&mut self) {
+        let current = self
+            .selected
+            .as_ref()
+            .and_then(|name| self.clients.get(name));
+        if !current.is_some_and(|client| client.present) {
+            let next = self
+                .clients
+                .iter()
+                .filter(|(name, client)| client.present && name.starts_with(&self.client_name))
+                .max_by_key(|(_, client)| client.seen)
+                .map(|(name, _)| name.clone());
+
+            if next != self.selected {
+                match next.as_ref() {
+                    Some(name) => {
+                        info!("Using {}", name);
+                        let _ = self.events.send(WorkerEvent::Found);
+                    }
+                    None => warn!("No {}* left, waiting for one.", self.client_name),
+                }
+                self.selected = next;
+            }
+        }
+
+        let osc = self
+            .selected
+            .as_ref()
+            .and_then(|name| self.clients.get(name))
+            .and_then(|client| client.osc);
+        if let Some(addr) = osc.filter(|addr| self.upstream != Some(*addr)) {
+            self.upstream = osc;
+            let _ = self.events.send(WorkerEvent::Upstream(addr));
+        }
+    }
+
+    fn url(&self) -> Option<&str> {
+        self.fixed_url.as_deref().or_else(|| {
+            let client = self.clients.get(self.selected.as_ref()?)?;
+            Some(client.url.as_str())
+        })
+    }
+
+    fn try_fetch(&mut self) {
+        let Some(url) = self.url() else {
+            return;
+        };
+        let addr = format!("{}/avatar", url);
+        let Some(pending) = self.pending.as_mut() else {
+            return;
+        };
+        if pending.next_attempt > Instant::now() {
+            return;
+        }
+
+        match fetch(&self.http, &addr, &self.config_dir) {
+            Ok(node) => {
+                if let Some(avatar_id) = pending.avatar_id.as_deref() {
+                    store_cached(&self.config_dir, avatar_id, &node);
+                }
+                let avatar_id = pending.avatar_id.take();
+                let _ = self.events.send(WorkerEvent::Fetched { avatar_id, node });
+                self.pending = None;
+            }
+            Err(e) if pending.attempts + 1 >= MAX_ATTEMPTS => {
+                warn!("Giving up on avatar parameters: {e:#}");
+                self.pending = None;
+            }
+            Err(e) => {
+                let delay = RETRY_DELAY * 2u32.pow(pending.attempts);
+                debug!("{e:#}, retrying in {} ms", delay.as_millis());
+                pending.attempts += 1;
+                pending.next_attempt = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+/// Where to reach a service announced with `addresses`, preferring IPv4. Link-local IPv6
+/// addresses are left out, as a URL can't carry the scope id they need.
+fn service_addr<A: ToString>(
+    addresses: impl IntoIterator<Item = A>,
+    port: u16,
+) -> Option<SocketAddr> {
+    addresses
+        .into_iter()
+        .filter_map(|addr| addr.to_string().split('%').next()?.parse::<IpAddr>().ok())
+        .filter(|ip| match ip {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(ip) => !ip.is_unicast_link_local(),
+        })
+        .min_by_key(|ip| (ip.is_ipv6(), *ip))
+        .map(|ip| SocketAddr::new(ip, port))
+}
+
+/// Where a client found at `service_ip` takes OSC. It reports an unspecified address when it
+/// listens everywhere, and a loopback one that only means something on its own machine.
+fn osc_target(osc: SocketAddr, service_ip: IpAddr) -> SocketAddr {
+    let remote_loopback = osc.ip().is_loopback() && !is_local(service_ip);
+    if osc.ip().is_unspecified() || remote_loopback {
+        SocketAddr::new(service_ip, osc.port())
+    } else {
+        osc
+    }
+}
+
+/// Whether `ip` belongs to this machine.
+fn is_local(ip: IpAddr) -> bool {
+    ip.is_loopback()
+        || if_addrs::get_if_addrs()
+            .is_ok_and(|interfaces| interfaces.iter().any(|interface| interface.ip() == ip))
+}
+
+/// `VRChat-Client-A1B2C3` out of `VRChat-Client-A1B2C3._oscjson._tcp.local.`
+fn instance_name(fullname: &str) -> &str {
+    fullname
+        .strip_suffix(SERVICE_TYPE)
+        .map_or(fullname, |name| name.trim_end_matches('.'))
+}
+
+#[derive(Deserialize)]
+struct HostInfo {
+    #[serde(rename = "OSC_IP")]
+    osc_ip: IpAddr,
+    #[serde(rename = "OSC_PORT")]
+    osc_port: u16,
+}
+
+/// Where an OSCQuery server's app takes OSC.
+fn host_info(client: &reqwest::blocking::Client, url: &str) -> anyhow::Result<SocketAddr> {
+    let json = client
+        .get(format!("{}/?HOST_INFO", url))
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.text())
+        .with_context(|| format!("Failed to fetch HOST_INFO from {}", url))?;
+    let info: HostInfo = serde_json::from_str(&json).context("Invalid HOST_INFO")?;
+    Ok(SocketAddr::new(info.osc_ip, info.osc_port))
+}
+
+fn fetch(
+    client: &reqwest::blocking::Client,
+    addr: &str,
+    config_dir: &str,
+) -> anyhow::Result<OscJsonNode> {
+    let json = client
+        .get(addr)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.text())
+        .with_context(|| format!("Failed to fetch avatar json from {}", addr))?;
+
+    let path = format!("{}/{}", config_dir, "oscavmgr-avatar.json");
+    if let Err(e) = fs::write(path, &json) {
+        warn!("Could not write avatar json file: {:?}", e);
+    }
+
+    parse_json(&json)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::core::harness::{avatar_json, config_dir, serve_oscquery};
+
+    #[test]
+    fn retries_until_the_avatar_is_served() {
+        let json = Arc::new(Mutex::new("not json yet".to_string()));
+        let port = serve_oscquery(json.clone());
+        let url = format!("http://127.0.0.1:{}", port);
+        let worker = OscJsonWorker::start(Some(&url), None, config_dir());
+        assert!(matches!(worker.try_recv(), Some(WorkerEvent::Found)));
+
+        worker.fetch(Some("avtr_retry".into()));
+        thread::sleep(FIRST_FETCH_DELAY * 2);
+        *json.lock().unwrap() = avatar_json(&[("VSync", "T")]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(WorkerEvent::Fetched { avatar_id, node }) = worker.try_recv() {
+                assert_eq!(avatar_id.as_deref(), Some("avtr_retry"));
+                assert!(node.has_vsync());
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        panic!("avatar was never fetched");
+    }
+
+    #[test]
+    fn follows_the_newest_matching_client() {
+        let (sender, events) = channel();
+        let mut worker = Worker::new(sender, Some("Tester"), config_dir());
+        let osc = |port| Some(SocketAddr::from(([127, 0, 0, 1], port)));
+
+        worker.resolved(
+            "VRChat-Client-A1B2C3",
+            "http://127.0.0.1:1".into(),
+            osc(9000),
+        );
+        worker.resolved("Tester-1", "http://127.0.0.1:2".into(), osc(9100));
+        worker.select();
+        assert_eq!(worker.url(), Some("http://127.0.0.1:2"));
+        assert!(matches!(events.try_recv(), Ok(WorkerEvent::Found)));
+        assert!(
+            matches!(events.try_recv(), Ok(WorkerEvent::Upstream(addr)) if addr.port() == 9100)
+        );
+
+        // a newer client does not take over while the picked one is around
+        worker.resolved("Tester-2", "http://127.0.0.1:3".into(), osc(9200));
+        worker.select();
+        assert_eq!(worker.url(), Some("http://127.0.0.1:2"));
+        assert!(events.try_recv().is_err());
+
+        worker.clients.get_mut("Tester-1").unwrap().present = false;
+        worker.select();
+        assert_eq!(worker.url(), Some("http://127.0.0.1:3"));
+        assert!(matches!(events.try_recv(), Ok(WorkerEvent::Found)));
+        assert!(
+            matches!(events.try_recv(), Ok(WorkerEvent::Upstream(addr)) if addr.port() == 9200)
+        );
+
+        worker.clients.get_mut("Tester-2").unwrap().present = false;
+        worker.select();
+        assert_eq!(worker.url(), None);
+        assert_eq!(
+            instance_name("VRChat-Client-A1B2C3._oscjson._tcp.local."),
+            "VRChat-Client-A1B2C3"
+        );
+    }
+
+    #[test]
+    fn reaches_clients_on_other_machines() {
+        let addresses = ["fe80::1", "2001:db8::1", "192.0.2.10"];
+        let addr = service_addr(addresses, 9001).unwrap();
+        assert_eq!(addr, SocketAddr::from(([192, 0, 2, 10], 9001)));
+
+        let addr = service_addr(["fe80::1%4", "2001:db8::1"], 9001).unwrap();
+        assert_eq!(format!("http://{}", addr), "http://[2001:db8::1]:9001");
+        assert_eq!(service_addr(["fe80::1"], 9001), None);
+
+        let remote: IpAddr = "192.0.2.10".parse().unwrap();
+        let local_only = SocketAddr::from(([127, 0, 0, 1], 9000));
+        let everywhere = SocketAddr::from(([0, 0, 0, 0], 9000));
+        let lan = SocketAddr::from(([192, 0, 2, 20], 9000));
+        let expected = SocketAddr::new(remote, 9000);
+        assert_eq!(osc_target(local_only, remote), expected);
+        assert_eq!(osc_target(everywhere, remote), expected);
+        assert_eq!(osc_target(lan, remote), lan);
+
+        // on this machine, loopback is where it listens
+        assert_eq!(osc_target(local_only, local_only.ip()), local_only);
+    }
+
+    #[test]
+    fn reads_where_the_client_takes_osc() {
+        let json = r#"{"NAME": "VRChat-Client-A1B2C3", "OSC_IP": "127.0.0.1", "OSC_PORT": 9010, "OSC_TRANSPORT": "UDP"}"#;
+        let port = serve_oscquery(Arc::new(Mutex::new(json.to_string())));
+        let http = reqwest::blocking::Client::new();
+
+        let osc = host_info(&http, &format!("http://127.0.0.1:{}", port)).unwrap();
+        assert_eq!(osc, SocketAddr::from(([127, 0, 0, 1], 9010)));
+    }
+}
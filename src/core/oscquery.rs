@@ -2,26 +2,38 @@ use anyhow::{bail, Context, Result};
 use hickory_proto::{
     op::{Message, MessageType, OpCode},
     rr::{
-        rdata::{A, PTR, SRV, TXT},
+        rdata::{A, AAAA, PTR, SRV, TXT},
         Name, RData, Record,
     },
     serialize::binary::{BinDecodable, BinEncodable},
 };
 use log::{info, warn};
-use once_cell::sync::Lazy;
-use serde::Serialize;
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fmt,
     io::{BufRead, BufReader, Read, Write},
-    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream, UdpSocket},
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, TcpStream,
+        UdpSocket,
+    },
     sync::{
         atomic::{AtomicU16, Ordering},
-        Arc,
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc, Mutex,
     },
     thread,
     time::Duration,
 };
+use websocket::{
+    header::{Headers, WebSocketAccept, WebSocketKey},
+    message::OwnedMessage,
+};
+
+use super::PARAM_PREFIX;
 
 const SERVICE_NAME: &str = "OscAvMgr";
 const OSC_HOSTNAME: &str = "OscAvMgr.osc.local.";
@@ -30,62 +42,221 @@ const OSC_SERVICE: &str = "_osc._udp.local.";
 const OSCQUERY_SERVICE: &str = "_oscjson._tcp.local.";
 const FIRST_HTTP_PORT: u16 = 9402;
 const MAX_REQUEST_LINE_LEN: u64 = 8192;
+const MAX_HEADERS: usize = 64;
 const MDNS_PORT: u16 = 5353;
 const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_ADDRESS_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
 const MDNS_RECORD_TTL: u32 = 4500;
 const MDNS_HOST_TTL: u32 = 120;
 const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local.";
+/// Messages queued for a websocket client before new ones are dropped
+const WS_QUEUE_LEN: usize = 256;
+
+/// What the HTTP thread serves, kept up to date by the OSC thread.
+#[derive(Default)]
+struct Values {
+    /// Avatar parameters oscavmgr sends, by name, with the last value sent
+    parameters: Mutex<BTreeMap<String, OscType>>,
+    /// Websocket clients, each with the addresses it LISTENs to
+    listeners: Mutex<Vec<Listener>>,
+}
+
+struct Listener {
+    paths: Arc<Mutex<BTreeSet<String>>>,
+    messages: SyncSender<OwnedMessage>,
+}
+
+impl Values {
+    fn record(&self, bundle: &OscBundle) {
+        let changed = record_parameters(&mut self.parameters.lock().unwrap(), bundle);
+        let mut listeners = self.listeners.lock().unwrap();
+        if changed.is_empty() || listeners.is_empty() {
+            return;
+        }
+
+        for msg in changed {
+            let Ok(buf) = rosc::encoder::encode(&OscPacket::Message(msg.clone())) else {
+                continue;
+            };
+            // a listener whose connection is gone no longer takes messages,
+            // and one that can't keep up misses some
+            listeners.retain(|listener| {
+                if !listener.paths.lock().unwrap().contains(&msg.addr) {
+                    return true;
+                }
+                match listener
+                    .messages
+                    .try_send(OwnedMessage::Binary(buf.clone()))
+                {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        log::debug!("OSCQuery websocket client is behind, dropping {}", msg.addr);
+                        true
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                }
+            });
+        }
+    }
+
+    /// The last value sent to `path`, as an OSC packet.
+    fn current(&self, path: &str) -> Option<Vec<u8>> {
+        let name = path.strip_prefix(PARAM_PREFIX)?;
+        let value = self.parameters.lock().unwrap().get(name)?.clone();
+        rosc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: path.to_string(),
+            args: vec![value],
+        }))
+        .ok()
+    }
+}
 
 pub struct OscQueryAdvert {
+    values: Arc<Values>,
     _http_thread: thread::JoinHandle<()>,
-    _mdns_thread: thread::JoinHandle<()>,
+    _mdns_threads: Vec<thread::JoinHandle<()>>,
 }
 
 impl OscQueryAdvert {
-    pub fn new(osc_port: u16) -> Result<Self> {
-        let listener = bind_first_available(FIRST_HTTP_PORT)
+    /// Serves OSCQuery on `ip`, the address oscavmgr takes OSC on.
+    pub fn new(ip: IpAddr, osc_port: u16) -> Result<Self> {
+        let listener = bind_first_available(ip, FIRST_HTTP_PORT)
             .context("could not bind an OSCQuery HTTP listener")?;
         let http_port = listener
             .local_addr()
             .context("could not read the OSCQuery HTTP listener address")?
             .port();
 
-        let mdns_socket = mdns_socket()?;
-        let services = vec![
-            MdnsService::new(OSC_SERVICE, OSC_HOSTNAME, osc_port)?,
-            MdnsService::new(OSCQUERY_SERVICE, OSCQUERY_HOSTNAME, http_port)?,
-        ];
+        let advertised_ip = advertised_ip(ip);
+        let services = Arc::new(vec![
+            MdnsService::new(OSC_SERVICE, OSC_HOSTNAME, osc_port, advertised_ip)?,
+            MdnsService::new(
+                OSCQUERY_SERVICE,
+                OSCQUERY_HOSTNAME,
+                http_port,
+                advertised_ip,
+            )?,
+        ]);
+
+        let mut mdns_sockets = Vec::new();
+        if ip.is_ipv4() {
+            mdns_sockets.push(mdns_socket_v4(ip)?);
+        }
+        if ip.is_ipv6() {
+            mdns_sockets.push(mdns_socket_v6(ip)?);
+        } else if ip.is_unspecified() || ip.is_loopback() {
+            // not bound to one interface: answer over IPv6 too, where it is available
+            match mdns_socket_v6(ip) {
+                Ok(socket) => mdns_sockets.push(socket),
+                Err(e) => info!("Not advertising over IPv6: {e:#}"),
+            }
+        }
 
-        let host_info = Arc::new(host_info_json(osc_port));
+        let host_info = Arc::new(host_info_json(advertised_ip, osc_port));
+        let values = Arc::new(Values::default());
         let http_thread = thread::Builder::new()
             .name("oscquery-http".into())
-            .spawn(move || serve(listener, host_info))
+            .spawn({
+                let values = values.clone();
+                move || serve(listener, host_info, values)
+            })
             .context("could not start the OSCQuery HTTP thread")?;
-        let mdns_thread = thread::Builder::new()
-            .name("oscquery-mdns".into())
-            .spawn(move || serve_mdns(mdns_socket, services))
-            .context("could not start the OSCQuery mDNS thread")?;
+        let mut mdns_threads = Vec::new();
+        for mdns_socket in mdns_sockets {
+            let services = services.clone();
+            let mdns_thread = thread::Builder::new()
+                .name("oscquery-mdns".into())
+                .spawn(move || serve_mdns(mdns_socket, &services))
+                .context("could not start the OSCQuery mDNS thread")?;
+            mdns_threads.push(mdns_thread);
+        }
 
         info!(
-            "Advertising {} through OSCQuery on TCP port {} (OSC port {})",
-            SERVICE_NAME, http_port, osc_port
+            "Advertising {} through OSCQuery on {} (OSC port {})",
+            SERVICE_NAME,
+            SocketAddr::new(advertised_ip, http_port),
+            osc_port
         );
 
         Ok(Self {
+            values,
             _http_thread: http_thread,
-            _mdns_thread: mdns_thread,
+            _mdns_threads: mdns_threads,
         })
     }
+
+    /// Serves the avatar parameters in `bundle` with their values, until the next avatar change,
+    /// and streams the changed ones to websocket listeners.
+    pub fn record(&self, bundle: &OscBundle) {
+        self.values.record(bundle);
+    }
+
+    /// Forgets the parameters of the previous avatar.
+    pub fn clear(&self) {
+        self.values.parameters.lock().unwrap().clear();
+    }
+}
+
+/// Updates `parameters` from `bundle`, returning the messages that changed a value.
+fn record_parameters<'a>(
+    parameters: &mut BTreeMap<String, OscType>,
+    bundle: &'a OscBundle,
+) -> Vec<&'a OscMessage> {
+    let mut changed = Vec::new();
+    for packet in bundle.content.iter() {
+        let OscPacket::Message(msg) = packet else {
+            continue;
+        };
+        if let (Some(name), [value]) = (msg.addr.strip_prefix(PARAM_PREFIX), &msg.args[..]) {
+            if parameters.insert(name.to_string(), value.clone()).as_ref() != Some(value) {
+                changed.push(msg);
+            }
+        }
+    }
+    changed
+}
+
+/// Where clients can reach oscavmgr: `ip`, or localhost if it listens everywhere.
+fn advertised_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) if v4.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+        IpAddr::V6(v6) if v6.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+        ip => ip,
+    }
 }
 
 struct MdnsSocket {
     socket: UdpSocket,
-    interfaces: Vec<Ipv4Addr>,
+    interfaces: Vec<MdnsInterface>,
+}
+
+/// Where the mDNS group was joined: by address for IPv4, by interface index for IPv6.
+#[derive(Clone, Copy)]
+enum MdnsInterface {
+    V4(Ipv4Addr),
+    V6(u32),
+}
+
+impl fmt::Display for MdnsInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdnsInterface::V4(ip) => write!(f, "{ip}"),
+            MdnsInterface::V6(index) => write!(f, "interface #{index}"),
+        }
+    }
+}
+
+fn network_interfaces() -> Vec<if_addrs::Interface> {
+    if_addrs::get_if_addrs().unwrap_or_else(|e| {
+        warn!("Could not enumerate network interfaces for OSCQuery: {e}");
+        Vec::new()
+    })
 }
 
-fn mdns_socket() -> Result<MdnsSocket> {
-    let mut interfaces = match if_addrs::get_if_addrs() {
-        Ok(interfaces) => interfaces
+fn mdns_socket_v4(ip: IpAddr) -> Result<MdnsSocket> {
+    let mut interfaces = match ip {
+        IpAddr::V4(ip) if !ip.is_unspecified() && !ip.is_loopback() => BTreeSet::from([ip]),
+        _ => network_interfaces()
             .into_iter()
             .filter_map(|interface| match interface.addr {
                 if_addrs::IfAddr::V4(address)
@@ -96,10 +267,6 @@ fn mdns_socket() -> Result<MdnsSocket> {
                 _ => None,
             })
             .collect::<BTreeSet<_>>(),
-        Err(e) => {
-            warn!("Could not enumerate network interfaces for OSCQuery: {e}");
-            BTreeSet::new()
-        }
     };
 
     if interfaces.is_empty() {
@@ -111,13 +278,7 @@ fn mdns_socket() -> Result<MdnsSocket> {
 
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
         .context("could not create the OSCQuery mDNS socket")?;
-    socket
-        .set_reuse_address(true)
-        .context("could not enable address reuse for OSCQuery mDNS")?;
-    #[cfg(target_family = "unix")]
-    socket
-        .set_reuse_port(true)
-        .context("could not enable port reuse for OSCQuery mDNS")?;
+    reuse_mdns_port(&socket)?;
     socket
         .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())
         .context("could not bind the OSCQuery mDNS socket")?;
@@ -131,7 +292,7 @@ fn mdns_socket() -> Result<MdnsSocket> {
     let mut joined = Vec::new();
     for interface in interfaces {
         match socket.join_multicast_v4(&MDNS_ADDRESS, &interface) {
-            Ok(()) => joined.push(interface),
+            Ok(()) => joined.push(MdnsInterface::V4(interface)),
             Err(e) => warn!("Failed to join the mDNS group on {interface}: {e}"),
         }
     }
@@ -145,6 +306,75 @@ fn mdns_socket() -> Result<MdnsSocket> {
     })
 }
 
+fn mdns_socket_v6(ip: IpAddr) -> Result<MdnsSocket> {
+    let specific = match ip {
+        IpAddr::V6(ip) if !ip.is_unspecified() && !ip.is_loopback() => Some(ip),
+        _ => None,
+    };
+    let mut interfaces = network_interfaces()
+        .into_iter()
+        .filter_map(|interface| match interface.addr {
+            if_addrs::IfAddr::V6(address)
+                if !address.ip.is_loopback() && specific.is_none_or(|ip| ip == address.ip) =>
+            {
+                interface.index
+            }
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    if interfaces.is_empty() {
+        if let Some(ip) = specific {
+            bail!("no network interface has {ip}");
+        }
+        // 0 asks the kernel to choose the default multicast interface
+        interfaces.insert(0);
+    }
+
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))
+        .context("could not create the OSCQuery IPv6 mDNS socket")?;
+    socket
+        .set_only_v6(true)
+        .context("could not make the OSCQuery mDNS socket IPv6 only")?;
+    reuse_mdns_port(&socket)?;
+    socket
+        .bind(&SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, MDNS_PORT, 0, 0).into())
+        .context("could not bind the OSCQuery IPv6 mDNS socket")?;
+    socket
+        .set_multicast_loop_v6(true)
+        .context("could not enable multicast loopback for OSCQuery IPv6 mDNS")?;
+    socket
+        .set_multicast_hops_v6(255)
+        .context("could not set the OSCQuery IPv6 mDNS multicast hop limit")?;
+
+    let mut joined = Vec::new();
+    for index in interfaces {
+        match socket.join_multicast_v6(&MDNS_ADDRESS_V6, index) {
+            Ok(()) => joined.push(MdnsInterface::V6(index)),
+            Err(e) => warn!("Failed to join the IPv6 mDNS group on interface #{index}: {e}"),
+        }
+    }
+    if joined.is_empty() {
+        bail!("could not join the mDNS group on any IPv6 interface");
+    }
+
+    Ok(MdnsSocket {
+        socket: socket.into(),
+        interfaces: joined,
+    })
+}
+
+fn reuse_mdns_port(socket: &Socket) -> Result<()> {
+    socket
+        .set_reuse_address(true)
+        .context("could not enable address reuse for OSCQuery mDNS")?;
+    #[cfg(target_family = "unix")]
+    socket
+        .set_reuse_port(true)
+        .context("could not enable port reuse for OSCQuery mDNS")?;
+    Ok(())
+}
+
 struct MdnsService {
     service_type: Name,
     instance_name: Name,
@@ -153,12 +383,12 @@ struct MdnsService {
 }
 
 impl MdnsService {
-    fn new(service_type: &str, hostname: &str, port: u16) -> Result<Self> {
+    fn new(service_type: &str, hostname: &str, port: u16, ip: IpAddr) -> Result<Self> {
         let service_type = Name::from_ascii(service_type).context("invalid mDNS service type")?;
         let instance_name = Name::from_ascii(format!("{SERVICE_NAME}.{service_type}"))
             .context("invalid mDNS service instance name")?;
         let hostname = Name::from_ascii(hostname).context("invalid mDNS hostname")?;
-        let response = service_response(&service_type, &instance_name, &hostname, port)?;
+        let response = service_response(&service_type, &instance_name, &hostname, port, ip)?;
 
         Ok(Self {
             service_type,
@@ -178,6 +408,7 @@ fn service_response(
     instance_name: &Name,
     hostname: &Name,
     port: u16,
+    ip: IpAddr,
 ) -> Result<Vec<u8>> {
     let mut response = mdns_response();
     response.add_answer(Record::from_rdata(
@@ -198,7 +429,10 @@ fn service_response(
     response.add_additional(Record::from_rdata(
         hostname.clone(),
         MDNS_HOST_TTL,
-        RData::A(A(Ipv4Addr::LOCALHOST)),
+        match ip {
+            IpAddr::V4(ip) => RData::A(A(ip)),
+            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+        },
     ));
     response
         .to_bytes()
@@ -229,13 +463,13 @@ fn mdns_response() -> Message {
     response
 }
 
-fn serve_mdns(mdns: MdnsSocket, services: Vec<MdnsService>) {
+fn serve_mdns(mdns: MdnsSocket, services: &[MdnsService]) {
     let enumeration_name = Name::from_ascii(SERVICE_ENUMERATION).unwrap();
-    let enumeration = enumeration_response(&services).unwrap();
+    let enumeration = enumeration_response(services).unwrap();
 
-    announce_services(&mdns, &services);
+    announce_services(&mdns, services);
     thread::sleep(Duration::from_secs(1));
-    announce_services(&mdns, &services);
+    announce_services(&mdns, services);
 
     let mut buffer = [0u8; 9000];
     loop {
@@ -290,7 +524,7 @@ fn send_query_response(
     response: &[u8],
     query: &Message,
     legacy_unicast: bool,
-    destination: Option<std::net::SocketAddr>,
+    destination: Option<SocketAddr>,
 ) {
     if legacy_unicast {
         match legacy_unicast_response(response, query) {
@@ -331,11 +565,7 @@ fn announce_services(mdns: &MdnsSocket, services: &[MdnsService]) {
     }
 }
 
-fn send_mdns_response(
-    mdns: &MdnsSocket,
-    response: &[u8],
-    unicast_destination: Option<std::net::SocketAddr>,
-) {
+fn send_mdns_response(mdns: &MdnsSocket, response: &[u8], unicast_destination: Option<SocketAddr>) {
     if let Some(destination) = unicast_destination {
         if let Err(e) = mdns.socket.send_to(response, destination) {
             warn!("Failed to send a unicast OSCQuery mDNS response: {e}");
@@ -343,10 +573,19 @@ fn send_mdns_response(
         return;
     }
 
-    let destination = SocketAddrV4::new(MDNS_ADDRESS, MDNS_PORT);
     for interface in &mdns.interfaces {
         let socket = SockRef::from(&mdns.socket);
-        if let Err(e) = socket.set_multicast_if_v4(interface) {
+        let (selected, destination) = match interface {
+            MdnsInterface::V4(ip) => (
+                socket.set_multicast_if_v4(ip),
+                SocketAddr::from((MDNS_ADDRESS, MDNS_PORT)),
+            ),
+            MdnsInterface::V6(index) => (
+                socket.set_multicast_if_v6(*index),
+                SocketAddr::from((MDNS_ADDRESS_V6, MDNS_PORT)),
+            ),
+        };
+        if let Err(e) = selected {
             warn!("Failed to select mDNS interface {interface}: {e}");
         } else if let Err(e) = mdns.socket.send_to(response, destination) {
             warn!("Failed to send an OSCQuery mDNS response on {interface}: {e}");
@@ -354,10 +593,10 @@ fn send_mdns_response(
     }
 }
 
-fn bind_first_available(first_port: u16) -> std::io::Result<TcpListener> {
+fn bind_first_available(ip: IpAddr, first_port: u16) -> std::io::Result<TcpListener> {
     let mut last_error = None;
     for port in first_port..=u16::MAX {
-        match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        match TcpListener::bind((ip, port)) {
             Ok(listener) => return Ok(listener),
             Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => last_error = Some(e),
             Err(e) => return Err(e),
@@ -372,37 +611,54 @@ fn bind_first_available(first_port: u16) -> std::io::Result<TcpListener> {
     }))
 }
 
-fn serve(listener: TcpListener, host_info: Arc<Vec<u8>>) {
+fn serve(listener: TcpListener, host_info: Arc<Vec<u8>>, values: Arc<Values>) {
     for stream in listener.incoming() {
         match stream {
-            Ok(stream) => handle_connection(stream, &host_info),
+            Ok(stream) => handle_connection(stream, &host_info, &values),
             Err(e) => warn!("Failed to accept an OSCQuery connection: {e}"),
         }
     }
 }
 
-fn handle_connection(mut stream: TcpStream, host_info: &[u8]) {
+fn handle_connection(mut stream: TcpStream, host_info: &[u8], values: &Arc<Values>) {
     let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
-    let mut request_line = String::new();
-    let mut reader = BufReader::new(&stream).take(MAX_REQUEST_LINE_LEN);
+    let Ok(read_half) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(read_half);
 
-    if reader.read_line(&mut request_line).is_err() || !request_line.ends_with('\n') {
+    let Some(request_line) = read_line(&mut reader) else {
         write_response(&mut stream, "400 Bad Request", "text/plain", b"Bad request");
         return;
-    }
+    };
 
     let Some(target) = request_target(&request_line) else {
         write_response(&mut stream, "400 Bad Request", "text/plain", b"Bad request");
         return;
     };
 
+    let headers = read_headers(&mut reader);
+    if headers
+        .get("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+    {
+        upgrade(
+            stream,
+            reader,
+            headers.get("sec-websocket-key").map(String::as_str),
+            values.clone(),
+        );
+        return;
+    }
+
     if target.contains("HOST_INFO") {
         write_response(&mut stream, "200 OK", "application/json", host_info);
         return;
     }
 
     let path = target.split('?').next().unwrap_or(target);
-    let Some(node) = ROOT.find(path) else {
+    let root = tree(&values.parameters.lock().unwrap());
+    let Some(node) = root.find(path) else {
         log::warn!("404 Not Found on: {}", request_line);
         write_response(
             &mut stream,
@@ -427,6 +683,132 @@ fn handle_connection(mut stream: TcpStream, host_info: &[u8]) {
     }
 }
 
+/// One line of the request, cut off at `MAX_REQUEST_LINE_LEN`.
+fn read_line(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    match reader
+        .by_ref()
+        .take(MAX_REQUEST_LINE_LEN)
+        .read_line(&mut line)
+    {
+        Ok(_) if line.ends_with('\n') => Some(line),
+        _ => None,
+    }
+}
+
+/// The request headers, with lowercase names.
+fn read_headers(reader: &mut BufReader<TcpStream>) -> BTreeMap<String, String> {
+    let mut headers = BTreeMap::new();
+    for _ in 0..MAX_HEADERS {
+        let Some(line) = read_line(reader) else {
+            break;
+        };
+        let Some((name, value)) = line.split_once(':') else {
+            break;
+        };
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    headers
+}
+
+/// Switches the connection over to a websocket, as the OSCQuery `LISTEN` extension describes.
+fn upgrade(
+    mut stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    key: Option<&str>,
+    values: Arc<Values>,
+) {
+    let Some(key) = key.and_then(|key| key.parse::<WebSocketKey>().ok()) else {
+        write_response(&mut stream, "400 Bad Request", "text/plain", b"Bad request");
+        return;
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        WebSocketAccept::new(&key).serialize()
+    );
+    if stream.write_all(response.as_bytes()).is_err() {
+        return;
+    }
+    let _ = stream.set_read_timeout(None);
+
+    let result = thread::Builder::new()
+        .name("oscquery-ws".into())
+        .spawn(move || stream_values(reader, values));
+    if let Err(e) = result {
+        warn!("Failed to start an OSCQuery websocket thread: {e}");
+    }
+}
+
+#[derive(Deserialize)]
+struct Command {
+    #[serde(rename = "COMMAND")]
+    command: String,
+    #[serde(rename = "DATA")]
+    data: String,
+}
+
+/// Sends the values of the addresses the client LISTENs to as binary OSC, whenever they change.
+fn stream_values(reader: BufReader<TcpStream>, values: Arc<Values>) {
+    let client = websocket::sync::Client::unchecked(reader, Headers::new(), false, true);
+    let (mut receiver, mut sender) = match client.split() {
+        Ok(halves) => halves,
+        Err(e) => {
+            warn!("OSCQuery websocket failed: {e}");
+            return;
+        }
+    };
+
+    let paths = Arc::new(Mutex::new(BTreeSet::new()));
+    let (messages, outgoing) = sync_channel(WS_QUEUE_LEN);
+    values.listeners.lock().unwrap().push(Listener {
+        paths: paths.clone(),
+        messages: messages.clone(),
+    });
+
+    // the OSC thread must never wait on a slow client
+    let writer = thread::spawn(move || {
+        for message in outgoing {
+            if sender.send_message(&message).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Ok(message) = receiver.recv_message() {
+        match message {
+            OwnedMessage::Text(text) => match serde_json::from_str::<Command>(&text) {
+                Ok(cmd) if cmd.command == "LISTEN" => {
+                    let current = values.current(&cmd.data);
+                    paths.lock().unwrap().insert(cmd.data);
+                    if let Some(buf) = current {
+                        let _ = messages.send(OwnedMessage::Binary(buf));
+                    }
+                }
+                Ok(cmd) if cmd.command == "IGNORE" => {
+                    paths.lock().unwrap().remove(&cmd.data);
+                }
+                _ => log::debug!("Unknown OSCQuery websocket command: {}", text),
+            },
+            OwnedMessage::Ping(data) => {
+                let _ = messages.send(OwnedMessage::Pong(data));
+            }
+            OwnedMessage::Close(_) => {
+                let _ = messages.send(OwnedMessage::Close(None));
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    values
+        .listeners
+        .lock()
+        .unwrap()
+        .retain(|listener| !Arc::ptr_eq(&listener.paths, &paths));
+    drop(messages);
+    let _ = writer.join();
+}
+
 fn request_target(request_line: &str) -> Option<&str> {
     let mut parts = request_line.split_whitespace();
     match (parts.next(), parts.next(), parts.next(), parts.next()) {
@@ -446,13 +828,13 @@ fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body
     let _ = stream.write_all(body);
 }
 
-fn host_info_json(osc_port: u16) -> Vec<u8> {
+fn host_info_json(osc_ip: IpAddr, osc_port: u16) -> Vec<u8> {
     #[derive(Serialize)]
     #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
     struct HostInfo {
         name: &'static str,
         extensions: BTreeMap<&'static str, bool>,
-        osc_ip: &'static str,
+        osc_ip: IpAddr,
         osc_port: u16,
         osc_transport: &'static str,
     }
@@ -460,6 +842,7 @@ fn host_info_json(osc_port: u16) -> Vec<u8> {
     let extensions = BTreeMap::from([
         ("ACCESS", true),
         ("CLIPMODE", false),
+        ("LISTEN", true),
         ("RANGE", true),
         ("TYPE", true),
         ("VALUE", true),
@@ -468,11 +851,11 @@ fn host_info_json(osc_port: u16) -> Vec<u8> {
     serde_json::to_vec(&HostInfo {
         name: SERVICE_NAME,
         extensions,
-        osc_ip: "127.0.0.1",
+        osc_ip,
         osc_port,
         osc_transport: "UDP",
     })
-    .expect("serializing static OSCQuery host info cannot fail")
+    .expect("serializing OSCQuery host info cannot fail")
 }
 
 #[derive(Serialize)]
@@ -480,36 +863,66 @@ struct OscQueryNode {
     #[serde(rename = "DESCRIPTION", skip_serializing_if = "Option::is_none")]
     description: Option<&'static str>,
     #[serde(rename = "FULL_PATH")]
-    full_path: &'static str,
+    full_path: String,
     #[serde(rename = "ACCESS")]
     access: u8,
     #[serde(rename = "CONTENTS", skip_serializing_if = "BTreeMap::is_empty")]
-    contents: BTreeMap<&'static str, OscQueryNode>,
+    contents: BTreeMap<String, OscQueryNode>,
     #[serde(rename = "TYPE", skip_serializing_if = "Option::is_none")]
     osc_type: Option<&'static str>,
+    #[serde(rename = "VALUE", skip_serializing_if = "Vec::is_empty")]
+    value: Vec<Value>,
+    #[serde(rename = "RANGE", skip_serializing_if = "Vec::is_empty")]
+    range: Vec<Value>,
 }
 
 impl OscQueryNode {
-    fn branch(full_path: &'static str, contents: BTreeMap<&'static str, Self>) -> Self {
+    fn branch<'a>(full_path: &str, contents: impl IntoIterator<Item = (&'a str, Self)>) -> Self {
         Self {
             description: None,
-            full_path,
+            full_path: full_path.to_string(),
             access: 0,
-            contents,
+            contents: contents
+                .into_iter()
+                .map(|(name, node)| (name.to_string(), node))
+                .collect(),
             osc_type: None,
+            value: Vec::new(),
+            range: Vec::new(),
         }
     }
 
-    fn endpoint(full_path: &'static str, osc_type: &'static str) -> Self {
+    fn endpoint(full_path: &str, osc_type: &'static str) -> Self {
         Self {
             description: Some(""),
-            full_path,
+            full_path: full_path.to_string(),
             access: 2,
             contents: BTreeMap::new(),
             osc_type: Some(osc_type),
+            value: Vec::new(),
+            range: Vec::new(),
         }
     }
 
+    /// A read-only parameter, with the range VRChat allows for its type.
+    fn parameter(full_path: &str, value: &OscType) -> Option<Self> {
+        let (osc_type, value, range) = match value {
+            OscType::Float(v) => ("f", json!(v), vec![json!({ "MIN": -1.0, "MAX": 1.0 })]),
+            OscType::Int(v) => ("i", json!(v), vec![json!({ "MIN": 0, "MAX": 255 })]),
+            OscType::Bool(v) => ("T", json!(v), Vec::new()),
+            _ => return None,
+        };
+        Some(Self {
+            description: None,
+            full_path: full_path.to_string(),
+            access: 1,
+            contents: BTreeMap::new(),
+            osc_type: Some(osc_type),
+            value: vec![value],
+            range,
+        })
+    }
+
     fn find(&self, path: &str) -> Option<&Self> {
         if path == "/" {
             return Some(self);
@@ -526,87 +939,196 @@ impl OscQueryNode {
     }
 }
 
-static ROOT: Lazy<OscQueryNode> = Lazy::new(|| {
+/// Everything oscavmgr takes, and the avatar parameters it sends.
+fn tree(parameters: &BTreeMap<String, OscType>) -> OscQueryNode {
+    let mut avatar_parameters = OscQueryNode::endpoint("/avatar/parameters", "b");
+    for (name, value) in parameters.iter() {
+        let mut node = &mut avatar_parameters;
+        let mut full_path = String::from("/avatar/parameters");
+        let parts: Vec<&str> = name.split('/').collect();
+        for (i, part) in parts.iter().enumerate() {
+            full_path = format!("{}/{}", full_path, part);
+            if i + 1 == parts.len() {
+                if let Some(parameter) = OscQueryNode::parameter(&full_path, value) {
+                    node.contents.insert(part.to_string(), parameter);
+                }
+                break;
+            }
+            node = node
+                .contents
+                .entry(part.to_string())
+                .or_insert_with(|| OscQueryNode::branch(&full_path, []));
+        }
+    }
+
     let avatar = OscQueryNode::branch(
         "/avatar",
-        BTreeMap::from([
+        [
             ("change", OscQueryNode::endpoint("/avatar/change", "s")),
-            (
-                "parameters",
-                OscQueryNode::endpoint("/avatar/parameters", "b"),
-            ),
-        ]),
+            ("parameters", avatar_parameters),
+        ],
     );
 
     let vrsystem = OscQueryNode::branch(
         "/tracking/vrsystem",
-        BTreeMap::from([
-            (
-                "head",
-                OscQueryNode::branch(
-                    "/tracking/vrsystem/head",
-                    BTreeMap::from([(
-                        "pose",
-                        OscQueryNode::endpoint("/tracking/vrsystem/head/pose", "ffffff"),
-                    )]),
-                ),
-            ),
-            (
-                "leftwrist",
-                OscQueryNode::branch(
-                    "/tracking/vrsystem/leftwrist",
-                    BTreeMap::from([(
-                        "pose",
-                        OscQueryNode::endpoint("/tracking/vrsystem/leftwrist/pose", "ffffff"),
-                    )]),
-                ),
-            ),
-            (
-                "rightwrist",
-                OscQueryNode::branch(
-                    "/tracking/vrsystem/rightwrist",
-                    BTreeMap::from([(
-                        "pose",
-                        OscQueryNode::endpoint("/tracking/vrsystem/rightwrist/pose", "ffffff"),
-                    )]),
-                ),
-            ),
-        ]),
+        ["head", "leftwrist", "rightwrist"].map(|tracker| {
+            let full_path = format!("/tracking/vrsystem/{tracker}");
+            let pose = OscQueryNode::endpoint(&format!("{full_path}/pose"), "ffffff");
+            (tracker, OscQueryNode::branch(&full_path, [("pose", pose)]))
+        }),
     );
 
-    let tracking = OscQueryNode::branch("/tracking", BTreeMap::from([("vrsystem", vrsystem)]));
+    let tracking = OscQueryNode::branch("/tracking", [("vrsystem", vrsystem)]);
 
     OscQueryNode {
         description: Some("root node"),
-        full_path: "/",
-        access: 0,
-        contents: BTreeMap::from([("avatar", avatar), ("tracking", tracking)]),
-        osc_type: None,
+        ..OscQueryNode::branch("/", [("avatar", avatar), ("tracking", tracking)])
     }
-});
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::bundle::AvatarBundle;
 
     #[test]
     fn advertises_avatar_and_tracking_endpoints() {
-        let avatar_change = ROOT.find("/avatar/change").unwrap();
+        let root = tree(&BTreeMap::new());
+        let avatar_change = root.find("/avatar/change").unwrap();
         assert_eq!(avatar_change.access, 2);
         assert_eq!(avatar_change.osc_type, Some("s"));
 
-        let avatar = ROOT.find("/avatar/parameters").unwrap();
+        let avatar = root.find("/avatar/parameters").unwrap();
         assert_eq!(avatar.access, 2);
         assert_eq!(avatar.osc_type, Some("b"));
 
         for tracker in ["head", "leftwrist", "rightwrist"] {
             let path = format!("/tracking/vrsystem/{tracker}/pose");
-            let endpoint = ROOT.find(&path).unwrap();
+            let endpoint = root.find(&path).unwrap();
             assert_eq!(endpoint.access, 2);
             assert_eq!(endpoint.osc_type, Some("ffffff"));
         }
     }
 
+    #[test]
+    fn serves_the_parameters_being_sent() {
+        let mut bundle = OscBundle::new_bundle();
+        bundle.send_parameter("FT/v2/JawOpen", OscType::Float(0.25));
+        bundle.send_parameter("Go/Locomotion", OscType::Bool(true));
+        bundle.send_parameter("ExtIndex", OscType::Int(3));
+        bundle.send_tracking("/tracking/eye/CenterVec", vec![OscType::Float(0.); 3]);
+        bundle.send_parameter("FT/v2/JawOpen", OscType::Float(0.5));
+
+        let mut parameters = BTreeMap::new();
+        assert_eq!(record_parameters(&mut parameters, &bundle).len(), 4);
+        assert_eq!(parameters.len(), 3);
+        let root = tree(&parameters);
+
+        let jaw = root.find("/avatar/parameters/FT/v2/JawOpen").unwrap();
+        assert_eq!(
+            serde_json::to_value(jaw).unwrap(),
+            json!({
+                "FULL_PATH": "/avatar/parameters/FT/v2/JawOpen",
+                "ACCESS": 1,
+                "TYPE": "f",
+                "VALUE": [0.5],
+                "RANGE": [{ "MIN": -1.0, "MAX": 1.0 }],
+            })
+        );
+        assert_eq!(
+            &root.find("/avatar/parameters/FT/v2").unwrap().full_path,
+            "/avatar/parameters/FT/v2"
+        );
+
+        let locomotion = root.find("/avatar/parameters/Go/Locomotion").unwrap();
+        assert_eq!(locomotion.value, [json!(true)]);
+        assert!(locomotion.range.is_empty());
+        assert_eq!(
+            root.find("/avatar/parameters/ExtIndex").unwrap().range,
+            [json!({ "MIN": 0, "MAX": 255 })]
+        );
+
+        // still takes any parameter
+        assert_eq!(root.find("/avatar/parameters").unwrap().access, 2);
+
+        let mut unchanged = OscBundle::new_bundle();
+        unchanged.send_parameter("Go/Locomotion", OscType::Bool(true));
+        assert!(record_parameters(&mut parameters, &unchanged).is_empty());
+    }
+
+    #[test]
+    fn streams_changes_to_websocket_listeners() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let values = Arc::new(Values::default());
+        thread::spawn({
+            let values = values.clone();
+            move || {
+                let host_info = host_info_json(Ipv4Addr::LOCALHOST.into(), 9002);
+                serve(listener, Arc::new(host_info), values)
+            }
+        });
+
+        let jaw = |value| {
+            let mut bundle = OscBundle::new_bundle();
+            bundle.send_parameter("JawOpen", OscType::Float(value));
+            bundle.send_parameter("MouthClosed", OscType::Float(value));
+            bundle
+        };
+        values.record(&jaw(0.25));
+
+        let mut ws = websocket::ClientBuilder::new(&format!("ws://127.0.0.1:{port}/"))
+            .unwrap()
+            .connect_insecure()
+            .unwrap();
+        ws.send_message(&websocket::Message::text(
+            r#"{"COMMAND": "LISTEN", "DATA": "/avatar/parameters/JawOpen"}"#,
+        ))
+        .unwrap();
+
+        let mut recv = || match ws.recv_message().unwrap() {
+            OwnedMessage::Binary(buf) => match rosc::decoder::decode_udp(&buf).unwrap().1 {
+                OscPacket::Message(msg) => (msg.addr, msg.args),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        };
+        // the value at the time of LISTEN, then every change
+        let expected = |value| {
+            (
+                "/avatar/parameters/JawOpen".to_string(),
+                vec![OscType::Float(value)],
+            )
+        };
+        assert_eq!(recv(), expected(0.25));
+
+        values.record(&jaw(0.25));
+        values.record(&jaw(0.5));
+        assert_eq!(recv(), expected(0.5));
+    }
+
+    #[test]
+    fn slow_listeners_miss_changes_instead_of_blocking() {
+        let values = Values::default();
+        let paths = Arc::new(Mutex::new(BTreeSet::from([
+            "/avatar/parameters/JawOpen".to_string()
+        ])));
+        let (messages, outgoing) = sync_channel(1);
+        values
+            .listeners
+            .lock()
+            .unwrap()
+            .push(Listener { paths, messages });
+
+        for value in [0.25, 0.5] {
+            let mut bundle = OscBundle::new_bundle();
+            bundle.send_parameter("JawOpen", OscType::Float(value));
+            values.record(&bundle);
+        }
+        assert_eq!(outgoing.try_iter().count(), 1);
+        assert_eq!(values.listeners.lock().unwrap().len(), 1);
+    }
+
     #[test]
     fn parses_only_get_requests() {
         assert_eq!(
@@ -625,7 +1147,7 @@ mod tests {
             return;
         }
 
-        let selected = bind_first_available(occupied_port).unwrap();
+        let selected = bind_first_available(Ipv4Addr::LOCALHOST.into(), occupied_port).unwrap();
         assert!(selected.local_addr().unwrap().port() > occupied_port);
     }
 }
@@ -0,0 +1,273 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use rosc::{OscBundle, OscPacket};
+use serde::{Deserialize, Serialize};
+
+/// A downstream OSC app that receives a copy of VRChat's traffic.
+///
+/// Written as `HOST:PORT` or `HOST:PORT=/prefix,/other/prefix`.
+/// A bare port is shorthand for `127.0.0.1:PORT`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RouteTarget {
+    pub addr: SocketAddr,
+    pub filters: Vec<Arc<str>>,
+}
+
+impl FromStr for RouteTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, filters) = match s.split_once('=') {
+            Some((addr, filters)) => (addr, Some(filters)),
+            None => (s, None),
+        };
+
+        let addr = match addr.parse::<u16>() {
+            Ok(port) => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port),
+            Err(_) => addr
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("invalid route address '{addr}': {e}"))?,
+        };
+
+        let filters = filters
+            .map(|filters| {
+                filters
+                    .split(',')
+                    .filter(|f| !f.is_empty())
+                    .map(|f| {
+                        if f.starts_with('/') {
+                            Ok(Arc::from(f))
+                        } else {
+                            Err(format!("route filter '{f}' must start with '/'"))
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self { addr, filters })
+    }
+}
+
+impl TryFrom<String> for RouteTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RouteTarget> for String {
+    fn from(target: RouteTarget) -> Self {
+        target.to_string()
+    }
+}
+
+impl fmt::Display for RouteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if !self.filters.is_empty() {
+            write!(f, "={}", self.filters.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+struct Route {
+    target: RouteTarget,
+    socket: UdpSocket,
+}
+
+/// Fans out VRChat's OSC traffic to other local apps, and merges whatever
+/// they send back into our upstream socket, so oscavmgr can be the only
+/// program that owns VRChat's OSC output port.
+#[derive(Default)]
+pub struct OscRouter {
+    routes: Vec<Route>,
+}
+
+impl OscRouter {
+    pub fn new(targets: &[RouteTarget], listen: Option<SocketAddr>, upstream: &UdpSocket) -> Self {
+        let mut routes = Vec::new();
+
+        for target in targets {
+            let bind_addr = match target.addr {
+                SocketAddr::V4(_) => "0.0.0.0:0",
+                SocketAddr::V6(_) => "[::]:0",
+            };
+            let socket = match UdpSocket::bind(bind_addr) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("Router: could not bind a socket for {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            // apps that reply to the sender address end up in VRChat too
+            spawn_relay(&socket, upstream, &target.to_string());
+
+            info!(
+                "Router: forwarding to {} from {}",
+                target,
+                socket
+                    .local_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default()
+            );
+            routes.push(Route {
+                target: target.clone(),
+                socket,
+            });
+        }
+
+        if let Some(listen) = listen {
+            match UdpSocket::bind(listen) {
+                Ok(socket) => {
+                    info!(
+                        "Router: merging OSC traffic received on {} into VRChat",
+                        listen
+                    );
+                    spawn_relay(&socket, upstream, "router input");
+                }
+                Err(e) => warn!("Router: could not listen on {}: {}", listen, e),
+            }
+        }
+
+        Self { routes }
+    }
+
+    /// Forwards a packet received from VRChat. `buf` must be the encoded `packet`.
+    pub fn forward(&self, buf: &[u8], packet: &OscPacket) {
+        for route in self.routes.iter() {
+            if route.target.filters.is_empty() {
+                if let Err(e) = route.socket.send_to(buf, route.target.addr) {
+                    log::debug!("Router: failed to send to {}: {}", route.target, e);
+                }
+                continue;
+            }
+
+            let Some(filtered) = filter_packet(packet, &route.target.filters) else {
+                continue;
+            };
+
+            match rosc::encoder::encode(&filtered) {
+                Ok(filtered_buf) => {
+                    if let Err(e) = route.socket.send_to(&filtered_buf, route.target.addr) {
+                        log::debug!("Router: failed to send to {}: {}", route.target, e);
+                    }
+                }
+                Err(e) => log::debug!("Router: failed to encode packet: {}", e),
+            }
+        }
+    }
+}
+
+fn spawn_relay(socket: &UdpSocket, upstream: &UdpSocket, name: &str) {
+    let (Ok(socket), Ok(upstream)) = (socket.try_clone(), upstream.try_clone()) else {
+        warn!("Router: could not clone sockets for {}", name);
+        return;
+    };
+    let name = name.to_string();
+
+    thread::spawn(move || {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((size, _)) => {
+                    if rosc::decoder::decode_udp(&buf[..size]).is_err() {
+                        log::debug!("Router: dropping non-OSC packet from {}", name);
+                        continue;
+                    }
+                    if let Err(e) = upstream.send(&buf[..size]) {
+                        log::debug!("Router: failed to relay packet from {}: {}", name, e);
+                    }
+                }
+                Err(e) => {
+                    warn!("Router: receive error on {}: {}", name, e);
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    });
+}
+
+/// Returns only the messages whose address starts with one of the `filters`,
+/// keeping the bundle structure intact.
+fn filter_packet(packet: &OscPacket, filters: &[Arc<str>]) -> Option<OscPacket> {
+    match packet {
+        OscPacket::Message(msg) => filters
+            .iter()
+            .any(|f| msg.addr.starts_with(f.as_ref()))
+            .then(|| packet.clone()),
+        OscPacket::Bundle(bundle) => {
+            let content: Vec<OscPacket> = bundle
+                .content
+                .iter()
+                .filter_map(|p| filter_packet(p, filters))
+                .collect();
+            (!content.is_empty()).then(|| {
+                OscPacket::Bundle(OscBundle {
+                    timetag: bundle.timetag,
+                    content,
+                })
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::bundle::AvatarBundle;
+    use rosc::{OscMessage, OscType};
+
+    #[test]
+    fn parses_route_targets() {
+        let plain: RouteTarget = "9010".parse().unwrap();
+        assert_eq!(plain.addr, "127.0.0.1:9010".parse().unwrap());
+        assert!(plain.filters.is_empty());
+
+        let filtered: RouteTarget = "192.168.1.5:9001=/avatar/parameters/,/chatbox"
+            .parse()
+            .unwrap();
+        assert_eq!(filtered.addr, "192.168.1.5:9001".parse().unwrap());
+        assert_eq!(filtered.filters.len(), 2);
+        assert_eq!(
+            filtered.to_string(),
+            "192.168.1.5:9001=/avatar/parameters/,/chatbox"
+        );
+
+        assert!("nope:9000".parse::<RouteTarget>().is_err());
+        assert!("9000=avatar".parse::<RouteTarget>().is_err());
+    }
+
+    #[test]
+    fn filters_bundles_by_prefix() {
+        let mut bundle = OscBundle::new_bundle();
+        bundle.send_parameter("VSync", OscType::Bool(true));
+        bundle.send_tracking("/tracking/vrsystem/head/pose", vec![OscType::Float(0.)]);
+        let packet = OscPacket::Bundle(bundle);
+
+        let filters = [Arc::from("/avatar/")];
+        let Some(OscPacket::Bundle(filtered)) = filter_packet(&packet, &filters) else {
+            panic!("expected a bundle");
+        };
+        assert_eq!(filtered.content.len(), 1);
+
+        let message = OscPacket::Message(OscMessage {
+            addr: "/tracking/vrsystem/head/pose".into(),
+            args: vec![],
+        });
+        assert!(filter_packet(&message, &filters).is_none());
+    }
+}
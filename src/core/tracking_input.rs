@@ -0,0 +1,220 @@
+use std::time::Instant;
+
+use glam::{Affine3A, EulerRot, Quat, Vec3};
+use rosc::{OscMessage, OscType};
+
+use super::OscTrack;
+
+pub const TRACK_PREFIX: &str = "/tracking/trackers/";
+pub const VRSYSTEM_PREFIX: &str = "/tracking/vrsystem/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedDevice {
+    Head,
+    LeftWrist,
+    RightWrist,
+}
+
+impl TrackedDevice {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "head" => Some(Self::Head),
+            "leftwrist" => Some(Self::LeftWrist),
+            "rightwrist" => Some(Self::RightWrist),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PoseUpdate {
+    Full(Vec3, Vec3),
+    Position(Vec3),
+    Rotation(Vec3),
+}
+
+/// Applies a VRChat tracking message to `tracking`.
+///
+/// Accepts both `/tracking/vrsystem/<device>/pose` (what VRChat sends when OSCQuery
+/// advertises it) and `/tracking/trackers/<device>[/position|/rotation]`.
+/// Returns false if the message is not a tracking message we understand.
+pub fn handle(packet: &OscMessage, tracking: &mut OscTrack) -> bool {
+    let Some((device, update)) = parse(packet) else {
+        return false;
+    };
+
+    let current = tracking.get(device);
+    let (_, rotation, translation) = current.to_scale_rotation_translation();
+
+    let transform = match update {
+        PoseUpdate::Full(position, euler) => unity_to_affine(position, euler),
+        PoseUpdate::Position(position) => {
+            Affine3A::from_rotation_translation(rotation, unity_to_position(position))
+        }
+        PoseUpdate::Rotation(euler) => {
+            Affine3A::from_rotation_translation(unity_to_rotation(euler), translation)
+        }
+    };
+
+    if device == TrackedDevice::Head {
+        tracking.last_received = Instant::now();
+    }
+    tracking.set(device, transform);
+    true
+}
+
+fn parse(packet: &OscMessage) -> Option<(TrackedDevice, PoseUpdate)> {
+    let (rest, vrsystem) = if let Some(rest) = packet.addr.strip_prefix(VRSYSTEM_PREFIX) {
+        (rest, true)
+    } else {
+        (packet.addr.strip_prefix(TRACK_PREFIX)?, false)
+    };
+
+    let mut parts = rest.splitn(2, '/');
+    let device = TrackedDevice::from_name(parts.next()?)?;
+    let suffix = parts.next().unwrap_or("");
+
+    let update = match (suffix, &packet.args[..]) {
+        (
+            "pose" | "",
+            [OscType::Float(x), OscType::Float(y), OscType::Float(z), OscType::Float(ex), OscType::Float(ey), OscType::Float(ez)],
+        ) => PoseUpdate::Full(Vec3::new(*x, *y, *z), Vec3::new(*ex, *ey, *ez)),
+        ("position", [OscType::Float(x), OscType::Float(y), OscType::Float(z)]) if !vrsystem => {
+            PoseUpdate::Position(Vec3::new(*x, *y, *z))
+        }
+        ("rotation", [OscType::Float(x), OscType::Float(y), OscType::Float(z)]) if !vrsystem => {
+            PoseUpdate::Rotation(Vec3::new(*x, *y, *z))
+        }
+        _ => {
+            log::debug!("Unsupported tracking message: {:?}", packet);
+            return None;
+        }
+    };
+
+    Some((device, update))
+}
+
+/// VRChat sends Unity coordinates: left-handed, +Z forward, euler angles in degrees
+/// applied in Z, X, Y order. Everything else in oscavmgr uses the right-handed
+/// OpenXR convention (-Z forward), so flip the Z axis.
+pub(crate) fn unity_to_affine(position: Vec3, euler_deg: Vec3) -> Affine3A {
+    Affine3A::from_rotation_translation(unity_to_rotation(euler_deg), unity_to_position(position))
+}
+
+pub(crate) fn unity_to_position(position: Vec3) -> Vec3 {
+    Vec3::new(position.x, position.y, -position.z)
+}
+
+pub(crate) fn unity_to_rotation(euler_deg: Vec3) -> Quat {
+    unity_quat_to_rotation(Quat::from_euler(
+        EulerRot::YXZ,
+        euler_deg.y.to_radians(),
+        euler_deg.x.to_radians(),
+        euler_deg.z.to_radians(),
+    ))
+}
+
+/// Mirrors a Unity quaternion across the Z axis. The same operation converts back.
+pub(crate) fn unity_quat_to_rotation(q: Quat) -> Quat {
+    Quat::from_xyzw(-q.x, -q.y, q.z, q.w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscPacket;
+
+    fn tracking() -> OscTrack {
+        OscTrack {
+            head: Affine3A::IDENTITY,
+            left_hand: Affine3A::IDENTITY,
+            right_hand: Affine3A::IDENTITY,
+            last_received: Instant::now(),
+        }
+    }
+
+    fn roundtrip(addr: &str, args: Vec<OscType>) -> OscMessage {
+        let buf = rosc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        }))
+        .unwrap();
+        match rosc::decoder::decode_udp(&buf).unwrap() {
+            (_, OscPacket::Message(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+
+    fn floats(values: &[f32]) -> Vec<OscType> {
+        values.iter().copied().map(OscType::Float).collect()
+    }
+
+    #[test]
+    fn parses_vrsystem_head_pose() {
+        let mut track = tracking();
+        let msg = roundtrip(
+            "/tracking/vrsystem/head/pose",
+            floats(&[0.5, 1.7, 2.0, 0., 90., 0.]),
+        );
+
+        assert!(handle(&msg, &mut track));
+        assert!(track
+            .head
+            .translation
+            .abs_diff_eq(Vec3::new(0.5, 1.7, -2.0).into(), 1e-5));
+
+        // turning right in Unity must still face right after the handedness flip
+        let forward = track.head.transform_vector3(Vec3::NEG_Z);
+        assert!(forward.abs_diff_eq(Vec3::X, 1e-5));
+    }
+
+    #[test]
+    fn parses_split_tracker_messages() {
+        let mut track = tracking();
+        let pos = roundtrip(
+            "/tracking/trackers/leftwrist/position",
+            floats(&[-0.3, 2.0, 0.1]),
+        );
+        let rot = roundtrip(
+            "/tracking/trackers/leftwrist/rotation",
+            floats(&[90., 0., 0.]),
+        );
+
+        assert!(handle(&pos, &mut track));
+        assert!(handle(&rot, &mut track));
+        assert!(track
+            .left_hand
+            .translation
+            .abs_diff_eq(Vec3::new(-0.3, 2.0, -0.1).into(), 1e-5));
+
+        // pitching down in Unity points the forward vector at the floor
+        let forward = track.left_hand.transform_vector3(Vec3::NEG_Z);
+        assert!(forward.abs_diff_eq(Vec3::NEG_Y, 1e-5));
+        assert_eq!(track.right_hand, Affine3A::IDENTITY);
+    }
+
+    #[test]
+    fn parses_legacy_tracker_pose() {
+        let mut track = tracking();
+        let msg = roundtrip(
+            "/tracking/trackers/rightwrist",
+            floats(&[0.2, 1.9, 0., 0., 0., 0.]),
+        );
+
+        assert!(handle(&msg, &mut track));
+        assert!(track.right_hand.translation.y > 1.8);
+    }
+
+    #[test]
+    fn ignores_unknown_messages() {
+        let mut track = tracking();
+        let unknown_device = roundtrip("/tracking/vrsystem/hip/pose", floats(&[0.; 6]));
+        let short = roundtrip("/tracking/vrsystem/head/pose", floats(&[0.; 3]));
+        let other = roundtrip("/avatar/parameters/VSync", vec![OscType::Bool(true)]);
+
+        assert!(!handle(&unknown_device, &mut track));
+        assert!(!handle(&short, &mut track));
+        assert!(!handle(&other, &mut track));
+        assert_eq!(track.head, Affine3A::IDENTITY);
+    }
+}
@@ -1,11 +1,14 @@
 #![allow(dead_code)]
 
-use crate::core::AvatarOsc;
+use crate::core::{config::Config, router::RouteTarget, AvatarOsc};
+
+use std::net::{IpAddr, SocketAddr};
 
 use clap::Parser;
 use env_logger::Env;
 use indicatif::MultiProgress;
 use indicatif_log_bridge::LogWrapper;
+use serde::{Deserialize, Serialize};
 
 mod core;
 
@@ -20,12 +23,40 @@ fn main() {
 
     let args = Args::parse();
 
-    let mut osc = AvatarOsc::new(args, multi);
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    if let Some(Command::Curves) = args.command {
+        print!("{}", config.curve_table());
+        return;
+    }
+
+    let mut osc = AvatarOsc::new(config, args, multi);
 
     osc.handle_messages();
 }
 
-#[derive(Default, Debug, Clone, clap::Subcommand)]
+#[derive(Debug, Clone, PartialEq, clap::Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Face(FaceSetup),
+
+    /// Print the response curve of every shape in the [curves] section, then exit
+    Curves,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, clap::Subcommand, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase", deny_unknown_fields)]
 pub enum FaceSetup {
     #[default]
     #[clap(subcommand, hide = true)]
@@ -44,31 +75,138 @@ pub enum FaceSetup {
     Babble {
         /// The port to listen on for Babble and ETVR packets.
         #[arg(short, long, default_value = "9400")]
+        #[serde(default = "default_babble_port")]
+        listen: u16,
+    },
+
+    /// Receive ARKit face data from the Live Link Face iPhone app
+    #[command(name = "livelink")]
+    LiveLink {
+        /// The port to listen on for Live Link packets.
+        #[arg(short, long, default_value = "11111")]
+        #[serde(default = "default_live_link_port")]
+        listen: u16,
+    },
+
+    /// Retrieve basic expressions from a webcam through OpenSeeFace
+    #[command(name = "openseeface")]
+    OpenSeeFace {
+        /// The port to listen on for OpenSeeFace packets.
+        #[arg(short, long, default_value = "11573")]
+        #[serde(default = "default_openseeface_port")]
+        listen: u16,
+    },
+
+    /// Receive ARKit / perfect sync blendshapes over the VMC protocol
+    Vmc {
+        /// The port to listen on for VMC packets.
+        #[arg(short, long, default_value = "39540")]
+        #[serde(default = "default_vmc_port")]
         listen: u16,
     },
+
+    /// Blink and glance around without an eye tracker, looking ahead of head turns
+    Procedural,
+
+    /// Play back a file made with --record instead of live tracking
+    Replay {
+        /// The recording to play
+        file: String,
+        /// Playback speed multiplier
+        #[arg(long, default_value = "1.0")]
+        #[serde(default = "default_replay_speed")]
+        speed: f32,
+        /// Start over at the end of the recording
+        #[arg(long = "loop", default_value_t = false)]
+        #[serde(default, rename = "loop")]
+        looping: bool,
+    },
+
+    /// Combine several providers, as set up in the [compositor] section of the config file
+    Composite,
+}
+
+#[cfg(feature = "babble")]
+fn default_babble_port() -> u16 {
+    9400
+}
+
+fn default_live_link_port() -> u16 {
+    11111
+}
+
+fn default_openseeface_port() -> u16 {
+    11573
+}
+
+fn default_vmc_port() -> u16 {
+    39540
+}
+
+fn default_replay_speed() -> f32 {
+    1.0
 }
 
 /// OSC Avatar Manager
-#[derive(Default, clap::Parser, Debug)]
+///
+/// Settings are read from ~/.config/oscavmgr.toml if it exists.
+/// Flags given on the command line take precedence over the file.
+#[derive(Default, Clone, clap::Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Provider to use for face data
+    /// Provider to use for face data, or another command
     #[command(subcommand)]
-    face: FaceSetup,
+    command: Option<Command>,
+
+    /// The configuration file to use [default: ~/.config/oscavmgr.toml]
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long, default_value_t = false)]
+    print_config: bool,
 
     /// Expose and lisen on 0.0.0.0, instead of localhost
     #[arg(long, default_value_t = false)]
     expose: bool,
 
-    /// OSC port for VRC
-    #[arg(long, default_value = "9000")]
-    vrc_port: u16,
+    /// Listen, send and advertise on this address or network interface instead of localhost
+    #[arg(long, value_name = "ADDR|INTERFACE")]
+    bind: Option<String>,
+
+    /// OSC port for VRC [default: 9000]
+    #[arg(long)]
+    vrc_port: Option<u16>,
+
+    /// Address of VRChat, when it runs on another machine or in a VM
+    #[arg(long, value_name = "IP")]
+    vrc_ip: Option<IpAddr>,
+
+    /// OSC listen port [default: 9002]
+    #[arg(long)]
+    osc_port: Option<u16>,
 
-    /// OSC listen port
-    #[arg(long, default_value = "9002")]
-    osc_port: u16,
+    /// Only talk to the VRChat client (or other OSCQuery app) whose name starts with this
+    #[arg(long, value_name = "NAME")]
+    vrc_client: Option<String>,
 
     /// The OSC-JSON avatar file to use. See ~/.config/oscavmgr-avatar.json
     #[arg(long)]
     avatar: Option<String>,
+
+    /// Forward VRChat's OSC output to another app: HOST:PORT[=/prefix,...] (repeatable)
+    #[arg(long = "route", value_name = "TARGET")]
+    routes: Vec<RouteTarget>,
+
+    /// Relay OSC received on this local port to VRChat, for apps that used to send to it directly
+    #[arg(long)]
+    router_port: Option<u16>,
+
+    /// Record face data and tracking poses to this file, for use with the replay provider
+    #[arg(long, value_name = "FILE")]
+    record: Option<String>,
+
+    /// Also send face tracking to a VMC receiver at this address (e.g. 127.0.0.1:39539)
+    #[arg(long, value_name = "ADDR")]
+    vmc: Option<SocketAddr>,
 }